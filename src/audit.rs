@@ -0,0 +1,291 @@
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use semver::{Version, VersionReq};
+
+use crate::{
+    discovery::Repo,
+    exclude::{ExcludeArgs, ExcludePaths},
+    lockfile::Lockfile,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct AuditArgs {
+    #[command(flatten)]
+    pub exclude_args: ExcludeArgs,
+
+    /// Local checkout of <https://github.com/rustsec/advisory-db>.
+    ///
+    /// Defaults to "advisory-db" in Cargo's home directory, which is where `cargo audit` keeps it.
+    /// Nothing is fetched, so update it yourself (e.g. with `git pull`) first.
+    #[arg(long, value_name = "PATH")]
+    pub advisory_db: Option<PathBuf>,
+}
+
+/// A RustSec advisory against a crate.
+pub struct Advisory {
+    pub id: String,
+    pub title: String,
+    /// Versions with the fix.
+    pub patched: Vec<VersionReq>,
+    /// Versions that never had the problem.
+    pub unaffected: Vec<VersionReq>,
+}
+
+impl Advisory {
+    pub fn affects(&self, version: &Version) -> bool {
+        !self
+            .patched
+            .iter()
+            .chain(&self.unaffected)
+            .any(|req| req.matches(version))
+    }
+}
+
+/// Every advisory in a local copy of the RustSec advisory database, by crate name.
+///
+/// Informational advisories (e.g. for unmaintained crates) and withdrawn ones are left out,
+/// since there's nothing to update to get rid of them.
+pub struct AdvisoryDb {
+    advisories: HashMap<String, Vec<Advisory>>,
+}
+
+/// The parts of an advisory's front matter that we care about.
+#[derive(serde::Deserialize)]
+struct AdvisoryFile {
+    advisory: AdvisoryMetadata,
+    #[serde(default)]
+    versions: AdvisoryVersions,
+}
+
+#[derive(serde::Deserialize)]
+struct AdvisoryMetadata {
+    id: String,
+    package: String,
+    /// Only in the old all-TOML format; newer advisories use a Markdown heading.
+    title: Option<String>,
+    informational: Option<String>,
+    withdrawn: Option<toml::Value>,
+}
+
+#[derive(serde::Deserialize, Default)]
+struct AdvisoryVersions {
+    #[serde(default)]
+    patched: Vec<VersionReq>,
+    #[serde(default)]
+    unaffected: Vec<VersionReq>,
+}
+
+impl AdvisoryDb {
+    /// Where `cargo audit` keeps its copy of the database.
+    pub fn default_path() -> anyhow::Result<PathBuf> {
+        let cargo_home = match std::env::var_os("CARGO_HOME") {
+            Some(cargo_home) => PathBuf::from(cargo_home),
+            None => PathBuf::from(
+                std::env::var_os("HOME").context("Couldn't find your home directory")?,
+            )
+            .join(".cargo"),
+        };
+        Ok(cargo_home.join("advisory-db"))
+    }
+
+    /// Read every advisory from a checkout of the database at `path`,
+    /// or the default location if there isn't one.
+    pub fn open(path: Option<&Path>) -> anyhow::Result<Self> {
+        let path = match path {
+            Some(path) => path.to_owned(),
+            None => Self::default_path()?,
+        };
+        let crates_dir = path.join("crates");
+        let crate_dirs = fs::read_dir(&crates_dir).with_context(|| {
+            format!("Couldn't find advisories in {path:?}; it should be a checkout of https://github.com/rustsec/advisory-db")
+        })?;
+
+        let mut advisories: HashMap<String, Vec<Advisory>> = HashMap::new();
+        for crate_dir in crate_dirs {
+            let crate_dir = crate_dir?.path();
+            if !crate_dir.is_dir() {
+                continue;
+            }
+            for file in fs::read_dir(&crate_dir)? {
+                let file = file?.path();
+                let is_advisory = file
+                    .extension()
+                    .is_some_and(|extension| extension == "md" || extension == "toml");
+                if !is_advisory {
+                    continue;
+                }
+                let contents = fs::read_to_string(&file)
+                    .with_context(|| format!("Failed to read {file:?}"))?;
+                // One bad file shouldn't hide every other advisory.
+                let AdvisoryFile { advisory, versions } = match parse_advisory(&contents) {
+                    Ok(advisory_file) => advisory_file,
+                    Err(err) => {
+                        eprintln!("Warning: skipping unparseable advisory {file:?}: {err:#}");
+                        continue;
+                    }
+                };
+                if advisory.informational.is_some() || advisory.withdrawn.is_some() {
+                    continue;
+                }
+                let title = advisory
+                    .title
+                    .or_else(|| markdown_title(&contents))
+                    .unwrap_or_default();
+                advisories
+                    .entry(advisory.package)
+                    .or_default()
+                    .push(Advisory {
+                        id: advisory.id,
+                        title,
+                        patched: versions.patched,
+                        unaffected: versions.unaffected,
+                    });
+            }
+        }
+        for crate_advisories in advisories.values_mut() {
+            crate_advisories.sort_by(|a, b| a.id.cmp(&b.id));
+        }
+        Ok(Self { advisories })
+    }
+
+    /// Every advisory that applies to a crates.io package in the lockfile.
+    ///
+    /// Crates from other registries might just share a name with one that has advisories.
+    pub fn vulnerabilities(&self, lockfile: &Lockfile) -> Vec<Vulnerability> {
+        let mut vulnerabilities = Vec::new();
+        for package in lockfile.crates_io_packages() {
+            let Some(advisories) = self.advisories.get(&package.name) else {
+                continue;
+            };
+            for advisory in advisories {
+                if advisory.affects(&package.version) {
+                    vulnerabilities.push(Vulnerability {
+                        advisory_id: advisory.id.clone(),
+                        title: advisory.title.clone(),
+                        crate_name: package.name.clone(),
+                        version: package.version.clone(),
+                        patched: advisory.patched.iter().map(VersionReq::to_string).collect(),
+                    });
+                }
+            }
+        }
+        vulnerabilities
+    }
+}
+
+/// Advisories are either TOML, or Markdown with TOML front matter in a code block.
+fn parse_advisory(contents: &str) -> anyhow::Result<AdvisoryFile> {
+    let front_matter = match contents.trim_start().strip_prefix("```toml") {
+        Some(rest) => rest
+            .split_once("\n```")
+            .map(|(front_matter, _)| front_matter)
+            .context("Front matter never ends")?,
+        None => contents,
+    };
+    Ok(toml::from_str(front_matter)?)
+}
+
+/// The first top-level heading after the front matter.
+fn markdown_title(contents: &str) -> Option<String> {
+    contents
+        .lines()
+        .skip_while(|line| !line.starts_with("```toml"))
+        .skip(1)
+        .skip_while(|line| !line.starts_with("```"))
+        .find_map(|line| line.strip_prefix("# "))
+        .map(|title| title.trim().to_string())
+}
+
+/// A package in a lockfile that an advisory applies to.
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct Vulnerability {
+    pub advisory_id: String,
+    pub title: String,
+    pub crate_name: String,
+    pub version: Version,
+    /// Requirements matching the versions with the fix; empty if there isn't one yet.
+    pub patched: Vec<String>,
+}
+
+impl Vulnerability {
+    /// Package ID spec that unambiguously identifies the package to `cargo update -p`.
+    pub fn spec(&self) -> String {
+        format!("{}@{}", self.crate_name, self.version)
+    }
+}
+
+impl fmt::Display for Vulnerability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} {} ({})",
+            self.advisory_id, self.crate_name, self.version, self.title
+        )
+    }
+}
+
+/// Every advisory that applies to a lockfile.
+pub struct ProjectAudit {
+    /// Directory containing the lockfile.
+    pub dir: PathBuf,
+    pub vulnerabilities: Vec<Vulnerability>,
+}
+
+/// Check every lockfile in the repo against the advisory database.
+pub fn audit(audit_args: &AuditArgs) -> anyhow::Result<Vec<ProjectAudit>> {
+    let exclude_paths = ExcludePaths::from_args(&audit_args.exclude_args)?;
+    let advisory_db = AdvisoryDb::open(audit_args.advisory_db.as_deref())
+        .context("Failed to read advisory database")?;
+
+    println!("Looking for Cargo projects...");
+    let repo = Repo::discover(&exclude_paths)?;
+
+    let mut audits = Vec::new();
+    for workspace in repo.lockfile_workspaces() {
+        let dir = &workspace.root;
+        let vulnerabilities = advisory_db.vulnerabilities(&Lockfile::read(dir)?);
+        if vulnerabilities.is_empty() {
+            println!("No advisories for anything in {dir:?}.");
+        } else {
+            println!("Vulnerable versions in {dir:?}:");
+            for vulnerability in &vulnerabilities {
+                if vulnerability.patched.is_empty() {
+                    println!("  {vulnerability}; no fixed version yet");
+                } else {
+                    println!(
+                        "  {vulnerability}; fixed in {}",
+                        vulnerability.patched.join(" or ")
+                    );
+                }
+            }
+        }
+        audits.push(ProjectAudit {
+            dir: dir.clone(),
+            vulnerabilities,
+        });
+    }
+    Ok(audits)
+}
+
+/// Describe what became of a lockfile's advisories for a commit message.
+pub fn commit_message_section(fixed: &[Vulnerability], remaining: &[Vulnerability]) -> String {
+    let mut section = String::new();
+    for (heading, vulnerabilities) in [
+        ("These advisories no longer apply", fixed),
+        ("These advisories still apply", remaining),
+    ] {
+        if vulnerabilities.is_empty() {
+            continue;
+        }
+        section += &format!("\n{heading}:\n\n");
+        for vulnerability in vulnerabilities {
+            section += &format!("- {vulnerability}\n");
+        }
+    }
+    section
+}
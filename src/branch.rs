@@ -0,0 +1,194 @@
+use anyhow::Context;
+
+use crate::git;
+
+#[derive(clap::Args, Debug)]
+pub struct BranchArgs {
+    /// Commit to this branch instead of a new timestamped one.
+    ///
+    /// If the branch already exists, it's reset to the base branch first,
+    /// and afterwards we report whether the result differs from what was there before.
+    #[arg(long, value_name = "NAME")]
+    pub branch: Option<String>,
+
+    /// Push the branch to origin when done, and track it from there.
+    ///
+    /// Refuses to overwrite a branch that already exists on origin
+    /// unless `--force-with-lease` is also given.
+    #[arg(long)]
+    pub push: bool,
+
+    /// Allow `--push` to overwrite the branch on origin,
+    /// as long as nobody else has pushed to it since we fetched it.
+    #[arg(long, requires = "push")]
+    pub force_with_lease: bool,
+}
+
+/// The branch that a run commits to.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Branch {
+    pub name: String,
+    /// What the branch pointed to before we started, if it already existed.
+    pub previous_commit: Option<String>,
+    /// What the branch pointed to on origin before we started, if it existed there.
+    #[serde(default)]
+    pub remote_commit: Option<String>,
+}
+
+/// How the branch compares to what was on it before.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BranchStatus {
+    /// The branch didn't exist before.
+    New,
+    /// The branch ended up with exactly the same files as before,
+    /// so there's no need to push it again.
+    Unchanged,
+    Changed,
+}
+
+impl Branch {
+    /// Fetch the base branch and start a branch from it to commit to.
+    ///
+    /// Without a `name`, this is a new branch named after `prefix` and the current time.
+    /// With one, the branch is reset to the base if it already exists
+    /// (preferring the copy on origin), so that we can tell afterwards
+    /// whether anything changed. That's refused for the base branch itself,
+    /// and for branches with commits that aren't on origin, unless we made them.
+    pub fn start(name: Option<&str>, prefix: &str) -> anyhow::Result<Self> {
+        let base_branch = git::guess_base_branch().context("Failed to guess base branch")?;
+        if name == Some(base_branch.as_str()) {
+            anyhow::bail!(
+                "Refusing to commit to the base branch {base_branch:?}; pick another name for `--branch`"
+            );
+        }
+        git::fetch(&base_branch).context("Failed to update base branch from origin")?;
+        let start_point = format!("origin/{base_branch}");
+
+        let Some(name) = name else {
+            // Make a branch based on the current time.
+            let compact_now = chrono::Utc::now().format("%Y%m%d%H%M%S");
+            let name = format!("{prefix}-{compact_now}");
+            git::switch_to_new_branch(&name, &start_point)
+                .with_context(|| format!("Failed to create branch {name:?}"))?;
+            return Ok(Self {
+                name,
+                previous_commit: None,
+                remote_commit: None,
+            });
+        };
+
+        let remote_commit = if git::fetch_if_exists(name)
+            .with_context(|| format!("Failed to fetch branch {name:?} from origin"))?
+        {
+            git::resolve_commit(&format!("origin/{name}"))?
+        } else {
+            None
+        };
+        if git::resolve_commit(&format!("refs/heads/{name}"))?.is_some() {
+            let unpushed = git::unpushed_commit_messages(name)
+                .with_context(|| format!("Failed to look for unpushed commits on {name:?}"))?;
+            // Our own commits will just be made again.
+            let others = unpushed
+                .iter()
+                .filter(|message| {
+                    !message.ends_with("This commit was created by `cargo-lockstep`.")
+                })
+                .count();
+            if others > 0 {
+                anyhow::bail!(
+                    "Branch {name:?} has {others} commits that aren't on origin, which resetting it would lose; push or delete them first"
+                );
+            }
+        }
+        let previous_commit = match &remote_commit {
+            Some(remote_commit) => Some(remote_commit.clone()),
+            None => git::resolve_commit(&format!("refs/heads/{name}"))?,
+        };
+        if previous_commit.is_some() {
+            println!("Resetting existing branch {name:?} onto {start_point:?}...");
+        }
+        git::reset_branch(name, &start_point)
+            .with_context(|| format!("Failed to reset branch {name:?}"))?;
+        Ok(Self {
+            name: name.to_string(),
+            previous_commit,
+            remote_commit,
+        })
+    }
+
+    /// Compare what's on the branch now to what was there before we started.
+    pub fn status(&self) -> anyhow::Result<BranchStatus> {
+        let Some(previous_commit) = &self.previous_commit else {
+            return Ok(BranchStatus::New);
+        };
+        if git::same_tree(previous_commit, "HEAD")
+            .context("Failed to compare branch with its previous contents")?
+        {
+            Ok(BranchStatus::Unchanged)
+        } else {
+            Ok(BranchStatus::Changed)
+        }
+    }
+
+    /// Work out how the branch changed, tell the user what to do next,
+    /// and maybe push it for them.
+    ///
+    /// An unchanged branch is only pushed if it isn't on origin yet,
+    /// and a new one without any commits on it never is.
+    pub fn finish(
+        &self,
+        push: bool,
+        force_with_lease: bool,
+        any_commits: bool,
+    ) -> anyhow::Result<BranchStatus> {
+        let status = self.status()?;
+        match status {
+            BranchStatus::Unchanged if push && self.remote_commit.is_none() => {
+                self.push(force_with_lease)?;
+                println!(
+                    "Branch {:?} ended up the same as it was before, but it wasn't on origin yet, so pushed it.",
+                    self.name
+                );
+            }
+            BranchStatus::Unchanged => {
+                println!(
+                    "Branch {:?} ended up the same as it was before, so there's nothing new to push.",
+                    self.name
+                );
+            }
+            BranchStatus::New if !any_commits => {}
+            BranchStatus::New | BranchStatus::Changed if push => {
+                self.push(force_with_lease)?;
+                println!("Pushed branch {:?} to origin.", self.name);
+            }
+            BranchStatus::New | BranchStatus::Changed => {
+                println!(
+                    "You can now push branch {:?} and make a pull-request.",
+                    self.name
+                );
+            }
+        }
+        Ok(status)
+    }
+
+    /// Push the branch to origin and track it from there.
+    ///
+    /// If the branch is already on origin, this refuses to overwrite it
+    /// unless `force_with_lease`, and even then only if it hasn't moved
+    /// since we last saw it.
+    pub fn push(&self, force_with_lease: bool) -> anyhow::Result<()> {
+        let remote_commit = match &self.remote_commit {
+            Some(remote_commit) => Some(remote_commit.clone()),
+            None => git::remote_branch_commit(&self.name)
+                .with_context(|| format!("Failed to check for branch {:?} on origin", self.name))?,
+        };
+        if remote_commit.is_some() && !force_with_lease {
+            anyhow::bail!(
+                "Branch {:?} already exists on origin; use `--force-with-lease` to overwrite it",
+                self.name
+            );
+        }
+        git::push(&self.name, remote_commit.as_deref())
+            .with_context(|| format!("Failed to push branch {:?}", self.name))
+    }
+}
@@ -1,10 +1,21 @@
-use std::{collections::HashMap, path::Path, process::Command};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    path::{Path, PathBuf},
+    process::Command,
+};
 
 use anyhow::Context;
 use semver::{Version, VersionReq};
 use tempfile::tempdir;
 
-use crate::command_ext::CommandExt as _;
+use crate::{command_ext::CommandExt as _, index};
+
+/// How Cargo identifies packages from crates.io, which is the only registry
+/// whose index we read.
+pub(crate) const CRATES_IO_SOURCES: &[&str] = &[
+    "registry+https://github.com/rust-lang/crates.io-index",
+    "sparse+https://index.crates.io/",
+];
 
 pub fn get_latest_versions(crate_names: &[String]) -> anyhow::Result<HashMap<String, Version>> {
     // Make a new Cargo project in a temporary directory.
@@ -15,7 +26,6 @@ pub fn get_latest_versions(crate_names: &[String]) -> anyhow::Result<HashMap<Str
     let mut cmd = Command::new("cargo");
     // Give it a valid crate name rather than letting it infer a name from the directory.
     cmd.args(["init", "--name", "dummy_for_querying_crate_versions"])
-        .null_io()
         .current_dir(tmp_dir.path());
     cmd.success_or_err().context("`cargo init` failed")?;
 
@@ -23,9 +33,15 @@ pub fn get_latest_versions(crate_names: &[String]) -> anyhow::Result<HashMap<Str
     let mut cmd = Command::new("cargo");
     cmd.args(["add", "--"])
         .args(crate_names)
-        .null_io()
         .current_dir(tmp_dir.path());
-    cmd.success_or_err().context("`cargo add` failed")?;
+    let output = cmd.clean_output()?;
+    if !output.status.success() {
+        // This is usually a typo on the command line, so say so plainly.
+        if let Some(crate_name) = unknown_crate_name(&String::from_utf8_lossy(&output.stderr)) {
+            return Err(index::unknown_crate(crate_name));
+        }
+        return Err(cmd.failure(&output)).context("`cargo add` failed");
+    }
 
     // Read the manifest to see what versions we ended up with.
     let manifest =
@@ -56,6 +72,14 @@ pub fn get_latest_versions(crate_names: &[String]) -> anyhow::Result<HashMap<Str
     Ok(result)
 }
 
+/// Pick the crate name out of Cargo's complaint about a crate that doesn't exist.
+fn unknown_crate_name(stderr: &str) -> Option<&str> {
+    let (_, rest) = stderr.split_once("the crate `")?;
+    let (crate_name, rest) = rest.split_once('`')?;
+    rest.starts_with(" could not be found")
+        .then_some(crate_name)
+}
+
 // TODO: Rationalize how you're managing paths.
 // Everything should be explicit, and probably just be paths to Cargo.toml or whatever.
 pub fn read_manifest(directory: &Path) -> anyhow::Result<Manifest> {
@@ -69,17 +93,22 @@ pub fn read_manifest(directory: &Path) -> anyhow::Result<Manifest> {
     Ok(manifest)
 }
 
-// TODO: Rationalize how you're managing paths.
-// Everything should be explicit, and probably just be paths to Cargo.toml or whatever.
+/// When resolving dependencies, every feature is turned on; otherwise optional dependencies
+/// are left out of the result even though they're in the lockfile.
 pub fn metadata(directory: &Path, no_deps: bool) -> anyhow::Result<Metadata> {
+    // TODO: Rationalize how you're managing paths.
+    // Everything should be explicit, and probably just be paths to Cargo.toml or whatever.
     let mut cmd = Command::new("cargo");
-    cmd.args(["metadata"]).current_dir(directory);
+    cmd.args(["metadata", "--format-version", "1"])
+        .current_dir(directory);
     if no_deps {
         cmd.arg("--no-deps");
+    } else {
+        cmd.arg("--all-features");
     }
     let output = cmd
         .output_if_success_else_err()
-        .context("`cargo read-manifest` failed")?;
+        .context("`cargo metadata` failed")?;
     let metadata: Metadata =
         serde_json::de::from_slice(&output.stdout).context("Failed to deserialize metadata")?;
     Ok(metadata)
@@ -87,14 +116,86 @@ pub fn metadata(directory: &Path, no_deps: bool) -> anyhow::Result<Metadata> {
 
 pub fn add(directory: &Path, crate_name: &str, extra_args: &[&str]) -> anyhow::Result<()> {
     let mut cmd = Command::new("cargo");
-    cmd.args(["add", crate_name])
-        .current_dir(directory)
-        .null_io();
+    cmd.args(["add", crate_name]).current_dir(directory);
     cmd.args(extra_args);
     cmd.success_or_err().context("`cargo add` failed")?;
     Ok(())
 }
 
+/// Run `cargo update`, returning its report of what changed.
+///
+/// Output is captured rather than shown so that it doesn't get jumbled up
+/// when updating several projects at once.
+pub fn update(directory: &Path) -> anyhow::Result<String> {
+    let mut cmd = Command::new("cargo");
+    cmd.arg("update").current_dir(directory);
+    let output = cmd
+        .output_if_success_else_err()
+        .context("`cargo update` failed")?;
+    Ok(String::from_utf8_lossy(&output.stderr).into_owned())
+}
+
+/// Run `cargo update` for just the given packages (as package ID specs).
+///
+/// Returns Cargo's report of what changed, like [`update`].
+pub fn update_packages(directory: &Path, package_specs: &[String]) -> anyhow::Result<String> {
+    let mut cmd = Command::new("cargo");
+    cmd.arg("update").current_dir(directory);
+    for package_spec in package_specs {
+        cmd.args(["--package", package_spec]);
+    }
+    let output = cmd
+        .output_if_success_else_err()
+        .context("`cargo update --package` failed")?;
+    Ok(String::from_utf8_lossy(&output.stderr).into_owned())
+}
+
+/// Run `cargo check --all-targets`.
+///
+/// Output is captured, and only shown if the check fails.
+pub fn check(directory: &Path) -> anyhow::Result<()> {
+    check_with_args(directory, &[])
+}
+
+/// Run `cargo check --all-targets` with extra arguments, e.g. to pick features.
+pub fn check_with_args(directory: &Path, extra_args: &[String]) -> anyhow::Result<()> {
+    let mut cmd = Command::new("cargo");
+    cmd.args(["check", "--all-targets"])
+        .args(extra_args)
+        .current_dir(directory);
+    cmd.success_or_err().context("`cargo check` failed")?;
+    Ok(())
+}
+
+/// Set a package in the lockfile to exactly the given version.
+pub fn update_precise(
+    directory: &Path,
+    package_spec: &str,
+    version: &Version,
+) -> anyhow::Result<()> {
+    let mut cmd = Command::new("cargo");
+    cmd.args(["update", "--package", package_spec, "--precise"])
+        .arg(version.to_string())
+        .current_dir(directory);
+    cmd.success_or_err()
+        .context("`cargo update --precise` failed")?;
+    Ok(())
+}
+
+/// Whether Cargo would consider these two versions compatible,
+/// i.e. whether a caret requirement for one could be satisfied by the other.
+pub fn is_semver_compatible(a: &Version, b: &Version) -> bool {
+    if a.major != b.major {
+        false
+    } else if a.major != 0 {
+        true
+    } else if a.minor != b.minor {
+        false
+    } else {
+        a.minor != 0 || a.patch == b.patch
+    }
+}
+
 // TODO: Replace this with metadata output
 
 #[derive(serde::Deserialize)]
@@ -107,16 +208,180 @@ pub struct Dependency {
     pub name: String,
     pub req: String,
     pub kind: Option<DepKind>,
+    /// Missing for path dependencies.
+    pub source: Option<String>,
+    #[serde(default)]
+    pub optional: bool,
+    /// The name the package uses for this dependency, if it's not the crate's own name.
+    #[serde(default)]
+    pub rename: Option<String>,
 }
 
+impl Dependency {
+    /// Whether this dependency comes from a registry (as opposed to a path or git dependency).
+    pub fn is_from_registry(&self) -> bool {
+        self.source
+            .as_deref()
+            .is_some_and(|source| source.starts_with("registry+") || source.starts_with("sparse+"))
+    }
+
+    /// Whether this dependency comes from crates.io, rather than some other registry.
+    pub fn is_from_crates_io(&self) -> bool {
+        self.source
+            .as_deref()
+            .is_some_and(|source| CRATES_IO_SOURCES.contains(&source))
+    }
+}
+
+/// Output of `cargo metadata`.
 #[derive(serde::Deserialize)]
 pub struct Metadata {
+    /// Every package in the workspace, plus all their dependencies
+    /// unless run with `--no-deps`.
     pub packages: Vec<Package>,
+    /// IDs of the packages that are members of the workspace.
+    #[serde(default)]
+    pub workspace_members: Vec<String>,
+    pub workspace_root: PathBuf,
+    /// The resolved dependency graph; missing when run with `--no-deps`.
+    pub resolve: Option<Resolve>,
+}
+
+impl Metadata {
+    pub fn package(&self, id: &str) -> Option<&Package> {
+        self.packages.iter().find(|package| package.id == id)
+    }
+
+    pub fn workspace_packages(&self) -> impl Iterator<Item = &Package> {
+        self.packages
+            .iter()
+            .filter(|package| self.workspace_members.contains(&package.id))
+    }
+
+    /// The shortest chain of dependencies from one package to another,
+    /// including both ends, by package ID.
+    ///
+    /// Always `None` without a resolved dependency graph.
+    pub fn shortest_path(&self, from: &str, to: &str) -> Option<Vec<&Package>> {
+        let nodes: HashMap<_, _> = self
+            .resolve
+            .as_ref()?
+            .nodes
+            .iter()
+            .map(|node| (node.id.as_str(), node))
+            .collect();
+
+        // Breadth-first, remembering how we got to each package.
+        let mut came_from = HashMap::from([(from, from)]);
+        let mut queue = VecDeque::from([from]);
+        while let Some(id) = queue.pop_front() {
+            if id == to {
+                let mut path = vec![id];
+                let mut id = id;
+                while id != from {
+                    id = came_from[id];
+                    path.push(id);
+                }
+                path.reverse();
+                return path.into_iter().map(|id| self.package(id)).collect();
+            }
+            for dep in nodes.get(id).map(|node| &node.deps[..]).unwrap_or_default() {
+                if !came_from.contains_key(dep.pkg.as_str()) {
+                    came_from.insert(&dep.pkg, id);
+                    queue.push_back(&dep.pkg);
+                }
+            }
+        }
+        None
+    }
+
+    /// The shortest chain of dependencies from any workspace member to a package.
+    pub fn path_from_workspace(&self, to: &str) -> Option<Vec<&Package>> {
+        self.workspace_members
+            .iter()
+            .filter_map(|member| self.shortest_path(member, to))
+            .min_by_key(|path| path.len())
+    }
 }
 
 #[derive(serde::Deserialize)]
 pub struct Package {
+    /// Opaque ID, used to refer to this package in [`Resolve`].
+    pub id: String,
+    pub name: String,
+    pub version: Version,
+    /// Missing for path dependencies and workspace members.
+    pub source: Option<String>,
+    pub manifest_path: PathBuf,
+    /// URL of the package's source repository, if its manifest says.
+    pub repository: Option<String>,
     pub dependencies: Vec<Dependency>,
+    /// The manifest's feature table, including features implied by optional dependencies.
+    #[serde(default)]
+    pub features: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub targets: Vec<Target>,
+}
+
+impl Package {
+    /// Whether this package comes from a registry (as opposed to being local or from Git).
+    pub fn is_from_registry(&self) -> bool {
+        self.source
+            .as_deref()
+            .is_some_and(|source| source.starts_with("registry+") || source.starts_with("sparse+"))
+    }
+
+    /// Whether this package comes from crates.io, rather than some other registry.
+    pub fn is_from_crates_io(&self) -> bool {
+        self.source
+            .as_deref()
+            .is_some_and(|source| CRATES_IO_SOURCES.contains(&source))
+    }
+}
+
+/// Something a package builds, e.g. a library, binary, test or build script.
+#[derive(serde::Deserialize)]
+pub struct Target {
+    pub name: String,
+    /// E.g. `["lib"]`, `["bin"]` or `["custom-build"]`.
+    pub kind: Vec<String>,
+    pub src_path: PathBuf,
+}
+
+#[derive(serde::Deserialize)]
+pub struct Resolve {
+    pub nodes: Vec<Node>,
+    /// The package in the current directory, unless it's a virtual workspace.
+    pub root: Option<String>,
+}
+
+/// A package in the resolved dependency graph.
+#[derive(serde::Deserialize)]
+pub struct Node {
+    pub id: String,
+    pub deps: Vec<NodeDep>,
+    /// Features enabled for this package.
+    #[serde(default)]
+    pub features: Vec<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct NodeDep {
+    /// Name the depending package uses for this dependency in its code.
+    pub name: String,
+    /// ID of the package depended on.
+    pub pkg: String,
+    /// How it's depended on; a dependency can be e.g. both normal and dev.
+    #[serde(default)]
+    pub dep_kinds: Vec<NodeDepKind>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct NodeDepKind {
+    /// `None` for normal dependencies.
+    pub kind: Option<DepKind>,
+    /// Platform the dependency is limited to, e.g. `cfg(windows)`.
+    pub target: Option<String>,
 }
 
 #[derive(serde::Deserialize)]
@@ -0,0 +1,163 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+
+use crate::{command_ext::CommandExt, config::Config, runner};
+
+/// A command to run after updating a project, to make sure it still works.
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Check {
+    /// Shell command to run in the project's directory,
+    /// e.g. `"cargo clippy --all-targets -- -D warnings"`.
+    pub run: String,
+    /// Give up on the check (and count it as failed) after this many seconds.
+    pub timeout: Option<u64>,
+}
+
+impl Check {
+    /// What `--check` does if nothing else is configured.
+    pub fn default_checks() -> Vec<Self> {
+        vec![Self {
+            run: "cargo check --all-targets".to_string(),
+            timeout: None,
+        }]
+    }
+}
+
+/// A check that passed.
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct CheckResult {
+    pub run: String,
+    /// Directory the check ran in.
+    pub dir: PathBuf,
+    pub duration: Duration,
+}
+
+impl fmt::Display for CheckResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`{}` in {:?} ({}s)",
+            self.run,
+            self.dir,
+            self.duration.as_secs()
+        )
+    }
+}
+
+/// Error for a check that ran and failed (or timed out), as opposed to
+/// something going wrong around it; see [`is_check_failure`].
+#[derive(Debug)]
+pub struct CheckFailed(pub String);
+
+impl fmt::Display for CheckFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Whether `err` means that the project is broken, rather than that we couldn't tell.
+pub fn is_check_failure(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<CheckFailed>().is_some()
+}
+
+/// The checks to run for the workspace in `dir`: its own if it has any
+/// configured, otherwise the repo-wide ones, otherwise `cargo check`.
+pub fn checks_for(config: &Config, dir: &Path) -> Vec<Check> {
+    let dir = normalize(dir);
+    config
+        .projects
+        .iter()
+        .find(|(project_dir, _)| normalize(project_dir) == dir)
+        .and_then(|(_, project)| project.checks.clone())
+        .or_else(|| (!config.checks.is_empty()).then(|| config.checks.clone()))
+        .unwrap_or_else(Check::default_checks)
+}
+
+/// Run each of `checks` in `dir`, in order, stopping at the first one that fails.
+///
+/// Progress goes into `log` rather than being printed, so that this can
+/// run alongside other projects.
+pub fn run_checks(
+    dir: &Path,
+    checks: &[Check],
+    log: &mut Vec<String>,
+) -> anyhow::Result<Vec<CheckResult>> {
+    let mut results = Vec::new();
+    for check in checks {
+        log.push(format!("  Running `{}` in {dir:?}...", check.run));
+        let mut cmd = shell_command(&check.run);
+        cmd.current_dir(dir);
+        let started = Instant::now();
+        let output = match check.timeout {
+            Some(timeout) => {
+                runner::runner().run_with_timeout(&mut cmd, Duration::from_secs(timeout))
+            }
+            None => runner::runner().run(&mut cmd),
+        };
+        let output = match output {
+            Ok(output) => output,
+            Err(err) if err.kind() == std::io::ErrorKind::TimedOut => {
+                return Err(anyhow::Error::msg(CheckFailed(format!(
+                    "Check `{}` timed out after {}s in {dir:?}",
+                    check.run,
+                    check.timeout.unwrap_or_default()
+                ))))
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to start check `{}`", check.run))
+            }
+        };
+        if !output.status.success() {
+            return Err(cmd.failure(&output))
+                .with_context(|| CheckFailed(format!("Check `{}` failed in {dir:?}", check.run)));
+        }
+        let result = CheckResult {
+            run: check.run.clone(),
+            dir: dir.to_owned(),
+            duration: started.elapsed(),
+        };
+        log.push(format!("    Passed in {}s", result.duration.as_secs()));
+        results.push(result);
+    }
+    Ok(results)
+}
+
+pub fn commit_message_section(results: &[CheckResult]) -> String {
+    if results.is_empty() {
+        return String::new();
+    }
+    let mut section = "\nThese checks passed:\n\n".to_string();
+    for result in results {
+        section += &format!("- {result}\n");
+    }
+    section
+}
+
+#[cfg(unix)]
+fn shell_command(script: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", script]);
+    cmd
+}
+
+#[cfg(windows)]
+fn shell_command(script: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.args(["/C", script]);
+    cmd
+}
+
+/// Make paths from the config file comparable with the ones discovery finds,
+/// which start with "./".
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != std::path::Component::CurDir)
+        .collect()
+}
@@ -1,53 +1,73 @@
-use std::process::{Command, ExitStatus, Output, Stdio};
+use std::process::{Command, ExitStatus, Output};
 
 use anyhow::Context;
 
+use crate::runner::{self, Invocation};
+
+/// Run commands through the current [`runner::Runner`],
+/// with errors that say what went wrong.
 pub trait CommandExt {
-    fn null_io(&mut self) -> &mut Command;
     fn clean_exit_status(&mut self) -> anyhow::Result<ExitStatus>;
     fn clean_exit_code(&mut self) -> anyhow::Result<i32>;
     fn success_or_err(&mut self) -> anyhow::Result<()>;
     fn clean_output(&mut self) -> anyhow::Result<Output>;
     fn output_if_success_else_err(&mut self) -> anyhow::Result<Output>;
+    /// An error for when this command failed, including the end of what it said about it.
+    fn failure(&self, output: &Output) -> anyhow::Error;
 }
 
-impl CommandExt for Command {
-    fn null_io(&mut self) -> &mut Command {
-        self.stdin(Stdio::null())
-            .stdout(Stdio::null())
-            .stderr(Stdio::null());
-
-        self
-    }
+/// How much of a failed command's output to include in errors.
+/// The interesting part is almost always at the end.
+const MAX_OUTPUT_LINES: usize = 20;
 
+impl CommandExt for Command {
     fn clean_exit_status(&mut self) -> anyhow::Result<ExitStatus> {
-        self.status()
-            .with_context(|| format!("Failed to start `{:?}`", self))
+        Ok(self.clean_output()?.status)
     }
 
     fn clean_exit_code(&mut self) -> anyhow::Result<i32> {
         self.clean_exit_status()?
             .code()
-            .with_context(|| format!("`{self:?}` was terminated by a signal"))
+            .with_context(|| format!("`{}` was terminated by a signal", Invocation::of(self)))
     }
 
     fn success_or_err(&mut self) -> anyhow::Result<()> {
-        if !self.clean_exit_status()?.success() {
-            anyhow::bail!("`{self:?}` returned a nonzero exit code");
-        }
+        self.output_if_success_else_err()?;
         Ok(())
     }
 
     fn clean_output(&mut self) -> anyhow::Result<Output> {
-        self.output()
-            .with_context(|| format!("Failed to start `{:?}`", self))
+        runner::runner()
+            .run(self)
+            .with_context(|| format!("Failed to start `{}`", Invocation::of(self)))
     }
 
     fn output_if_success_else_err(&mut self) -> anyhow::Result<Output> {
         let output = self.clean_output()?;
         if !output.status.success() {
-            anyhow::bail!("`{self:?}` returned a nonzero exit code");
+            return Err(self.failure(&output));
         }
         Ok(output)
     }
+
+    fn failure(&self, output: &Output) -> anyhow::Error {
+        // Most tools explain themselves on stderr, but some only use stdout.
+        let mut explanation = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        if explanation.is_empty() {
+            explanation = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        }
+        let mut message = format!("`{}` failed ({})", Invocation::of(self), output.status);
+        if !explanation.is_empty() {
+            let lines: Vec<_> = explanation.lines().collect();
+            message += ":\n";
+            if lines.len() > MAX_OUTPUT_LINES {
+                message += &format!(
+                    "[{} earlier lines omitted]\n",
+                    lines.len() - MAX_OUTPUT_LINES
+                );
+            }
+            message += &lines[lines.len().saturating_sub(MAX_OUTPUT_LINES)..].join("\n");
+        }
+        anyhow::anyhow!(message)
+    }
 }
@@ -0,0 +1,54 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+use crate::check::Check;
+
+/// Name of the config file, which is read from the current directory.
+pub const CONFIG_FILE_NAME: &str = "cargo-lockstep.toml";
+
+/// Repo-wide settings for `cargo-lockstep`.
+///
+/// All fields are optional, and it's fine for the file not to exist at all.
+#[derive(serde::Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Named sets of crates that must always be upgraded together.
+    ///
+    /// Members may end with `*` to match every dependency with that prefix,
+    /// e.g. `bevy = ["bevy", "bevy_*"]`.
+    #[serde(default)]
+    pub groups: BTreeMap<String, Vec<String>>,
+
+    /// Commands that `--check` runs after each update, in order.
+    ///
+    /// Defaults to just `cargo check --all-targets`.
+    #[serde(default)]
+    pub checks: Vec<Check>,
+
+    /// Settings for individual workspaces, by directory (e.g. `"tools/xtask"`).
+    #[serde(default)]
+    pub projects: BTreeMap<PathBuf, ProjectConfig>,
+}
+
+#[derive(serde::Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectConfig {
+    /// Checks to run for this workspace instead of the repo-wide ones.
+    pub checks: Option<Vec<Check>>,
+}
+
+impl Config {
+    pub fn load() -> anyhow::Result<Self> {
+        let path = Path::new(CONFIG_FILE_NAME);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents =
+            std::fs::read_to_string(path).with_context(|| format!("Failed to read {path:?}"))?;
+        toml::from_str(&contents).with_context(|| format!("Failed to parse {path:?}"))
+    }
+}
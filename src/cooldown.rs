@@ -0,0 +1,194 @@
+use std::{collections::HashSet, fmt, path::Path};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use semver::Version;
+
+use crate::{
+    cargo,
+    index::{Index, IndexEntry},
+    lockfile::Lockfile,
+};
+
+/// Policy for refusing to adopt versions that were published too recently.
+///
+/// Fresh releases are the ones most likely to get yanked,
+/// so it's often worth waiting a few days before picking them up.
+pub struct Cooldown {
+    min_age_days: u32,
+    cutoff: DateTime<Utc>,
+}
+
+/// A package that we kept on an older version because its newest version was too new.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct HeldBack {
+    pub crate_name: String,
+    pub too_new: Version,
+    /// `None` if we couldn't find anything old enough that Cargo would accept.
+    pub kept: Option<Version>,
+}
+
+impl fmt::Display for HeldBack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kept {
+            Some(kept) => write!(f, "{} {} (skipped {})", self.crate_name, kept, self.too_new),
+            None => write!(
+                f,
+                "{} {} (too new, but no older alternative was usable)",
+                self.crate_name, self.too_new
+            ),
+        }
+    }
+}
+
+impl Cooldown {
+    pub fn new(min_age_days: u32) -> Self {
+        Self {
+            min_age_days,
+            cutoff: Utc::now() - TimeDelta::days(min_age_days.into()),
+        }
+    }
+
+    pub fn min_age_days(&self) -> u32 {
+        self.min_age_days
+    }
+
+    /// Whether this version was published too recently to adopt.
+    ///
+    /// Versions without a publish time are assumed to be old enough,
+    /// since older index entries don't record one.
+    pub fn is_too_new(&self, entry: &IndexEntry) -> bool {
+        entry.pubtime.is_some_and(|pubtime| pubtime > self.cutoff)
+    }
+
+    /// Find the newest normal release of a crate that is old enough,
+    /// isn't yanked, and satisfies `predicate`.
+    pub fn newest_allowed_version(
+        &self,
+        index: &Index,
+        crate_name: &str,
+        predicate: impl Fn(&Version) -> bool,
+    ) -> anyhow::Result<Option<Version>> {
+        index.newest_release(crate_name, |entry| {
+            !self.is_too_new(entry) && predicate(&entry.vers)
+        })
+    }
+
+    /// Undo any changes to the lockfile in `directory` that adopted versions
+    /// that are too new, by pinning them back to the newest compatible version
+    /// that is old enough (or to what was in the lockfile before).
+    pub fn hold_back_lockfile(
+        &self,
+        index: &Index,
+        directory: &Path,
+        before: &Lockfile,
+        log: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<HeldBack>> {
+        let mut held_back = Vec::new();
+        let mut stuck = Vec::new();
+        let mut checked = HashSet::new();
+
+        // Holding back one package can shuffle others around
+        // (e.g. dropping a dependency that was only needed by the newer version),
+        // so we re-read the lockfile after every change and handle one package at a time.
+        loop {
+            let after = Lockfile::read(directory)?;
+            let mut next = None;
+            for package in after.new_crates_io_packages_since(before) {
+                if !checked.insert(package.spec()) {
+                    continue;
+                }
+                let Some(entry) = index.entry(&package.name, &package.version)? else {
+                    log.push(format!(
+                        "    Warning: {} isn't in the registry index; can't check its age",
+                        package.spec()
+                    ));
+                    continue;
+                };
+                if self.is_too_new(&entry) {
+                    next = Some(package.clone());
+                    break;
+                }
+            }
+            let Some(package) = next else {
+                break;
+            };
+
+            // Never go back further than what we had before, if we had anything.
+            let previous = before
+                .registry_packages()
+                .filter(|old| old.name == package.name)
+                .filter(|old| cargo::is_semver_compatible(&old.version, &package.version))
+                .map(|old| old.version.clone())
+                .max();
+            // Getting off a yanked version is more important than waiting out the cooldown,
+            // so we never go back to one of those.
+            let previous_is_yanked = match &previous {
+                Some(previous) => index
+                    .entry(&package.name, previous)?
+                    .is_some_and(|entry| entry.yanked),
+                None => false,
+            };
+            let fallback = self
+                .newest_allowed_version(index, &package.name, |version| {
+                    version < &package.version
+                        && cargo::is_semver_compatible(version, &package.version)
+                        && previous.as_ref().is_none_or(|previous| {
+                            version > previous || (version == previous && !previous_is_yanked)
+                        })
+                })?
+                .or_else(|| previous.clone().filter(|_| !previous_is_yanked));
+
+            if fallback.is_none() && previous_is_yanked {
+                log.push(format!(
+                    "    Not holding back {} because the previous version is yanked",
+                    package.spec()
+                ));
+                continue;
+            }
+
+            match fallback {
+                Some(fallback)
+                    if cargo::update_precise(directory, &package.spec(), &fallback).is_ok() =>
+                {
+                    held_back.push(HeldBack {
+                        crate_name: package.name,
+                        too_new: package.version,
+                        kept: Some(fallback),
+                    });
+                }
+                _ => stuck.push(package),
+            }
+        }
+
+        // Only complain about packages we couldn't hold back if they're still there;
+        // holding back something else may have made them go away.
+        let after = Lockfile::read(directory)?;
+        held_back.extend(
+            stuck
+                .into_iter()
+                .filter(|package| after.packages.contains(package))
+                .map(|package| HeldBack {
+                    crate_name: package.name,
+                    too_new: package.version,
+                    kept: None,
+                }),
+        );
+
+        Ok(held_back)
+    }
+}
+
+/// Describe held-back packages for a commit message.
+pub fn commit_message_section(cooldown: &Cooldown, held_back: &[HeldBack]) -> String {
+    if held_back.is_empty() {
+        return String::new();
+    }
+    let mut section = format!(
+        "\nThese were held back because their newest releases are less than {} days old:\n\n",
+        cooldown.min_age_days()
+    );
+    for held_back in held_back {
+        section += &format!("- {held_back}\n");
+    }
+    section
+}
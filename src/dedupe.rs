@@ -0,0 +1,360 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use semver::Version;
+
+use crate::{
+    branch::Branch,
+    cargo::{self, Metadata},
+    discovery::Repo,
+    exclude::{ExcludeArgs, ExcludePaths},
+    git,
+    index::Index,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct DedupeArgs {
+    #[command(flatten)]
+    pub exclude_args: ExcludeArgs,
+
+    /// Apply the suggested updates rather than just printing them,
+    /// committing each lockfile separately on a new branch.
+    #[arg(long)]
+    pub apply: bool,
+}
+
+/// A package in a lockfile, as "name version".
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PackageVersion {
+    pub name: String,
+    pub version: Version,
+}
+
+impl PackageVersion {
+    /// Package ID spec that unambiguously identifies this package to `cargo update -p`.
+    pub fn spec(&self) -> String {
+        format!("{}@{}", self.name, self.version)
+    }
+}
+
+impl fmt::Display for PackageVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.name, self.version)
+    }
+}
+
+/// A crate that a lockfile has more than one version of.
+pub struct Duplicate {
+    pub crate_name: String,
+    /// Every version in the lockfile, and what depends on each of them.
+    pub dependents: BTreeMap<Version, Vec<Dependent>>,
+}
+
+impl Duplicate {
+    /// The version everything else should move to.
+    pub fn newest(&self) -> &Version {
+        self.dependents
+            .keys()
+            .next_back()
+            .expect("duplicates have at least two versions")
+    }
+}
+
+/// Something that depends on a particular version of a duplicated crate.
+pub struct Dependent {
+    pub package: PackageVersion,
+    /// Whether this is one of our own packages rather than one from a registry.
+    pub is_local: bool,
+    /// Whether this is from crates.io, so we can look for other releases of it.
+    pub is_from_crates_io: bool,
+}
+
+/// What it would take to stop a dependent from using an old version of a duplicated crate.
+pub enum Fix {
+    /// A newer compatible release of the dependent uses the newest version.
+    UpdatePrecise {
+        dependent: PackageVersion,
+        to: Version,
+    },
+    /// One of our own packages needs its requirement upgraded.
+    UpgradeRequirement { dependent: PackageVersion },
+    /// No compatible release of the dependent uses the newest version.
+    Stuck { dependent: PackageVersion },
+}
+
+impl Fix {
+    fn describe(&self, crate_name: &str, newest: &Version) -> String {
+        match self {
+            Self::UpdatePrecise { dependent, to } => format!(
+                "{dependent}: update to {to} (`cargo update -p {} --precise {to}`)",
+                dependent.spec()
+            ),
+            Self::UpgradeRequirement { dependent } => format!(
+                "{dependent}: upgrade its requirement (`cargo-lockstep upgrade {crate_name}`)"
+            ),
+            Self::Stuck { dependent } => format!(
+                "{dependent}: no compatible release uses {crate_name} {newest}; needs a semver-incompatible upgrade"
+            ),
+        }
+    }
+}
+
+/// Everything that `dedupe` found (and maybe fixed) in a lockfile.
+pub struct LockfileDuplicates {
+    pub dir: PathBuf,
+    pub duplicates: Vec<Duplicate>,
+    /// Whether fixes were applied and committed.
+    pub committed: bool,
+}
+
+/// Find crates with more than one version in each lockfile, and suggest
+/// (or apply) the updates that would let them share one.
+pub fn dedupe(dedupe_args: &DedupeArgs) -> anyhow::Result<Vec<LockfileDuplicates>> {
+    let exclude_paths = ExcludePaths::from_args(&dedupe_args.exclude_args)?;
+
+    let branch = if dedupe_args.apply {
+        if !git::is_working_tree_clean().context("Failed to check if working tree is clean")? {
+            anyhow::bail!("Working tree is not clean; please commit or stash your changes first.");
+        }
+        Some(
+            Branch::start(None, "cargo-lockstep-dedupe")
+                .context("Failed to create branch for deduplicating")?,
+        )
+    } else {
+        None
+    };
+
+    println!("Looking for Cargo projects...");
+    let repo = Repo::discover(&exclude_paths)?;
+    let index = Index::from_env();
+
+    let mut reports = Vec::new();
+    for workspace in repo.lockfile_workspaces() {
+        let dir = &workspace.root;
+        let duplicates = find_duplicates(dir)?;
+        if duplicates.is_empty() {
+            continue;
+        }
+
+        println!("Duplicates in {dir:?}:");
+        let mut fixes = Vec::new();
+        for duplicate in &duplicates {
+            let versions: Vec<_> = duplicate
+                .dependents
+                .keys()
+                .map(Version::to_string)
+                .collect();
+            println!("  {} {}", duplicate.crate_name, versions.join(", "));
+            for (version, dependents) in &duplicate.dependents {
+                if version == duplicate.newest() {
+                    continue;
+                }
+                println!("    {version} is used by:");
+                for dependent in dependents {
+                    let fix = suggest_fix(&index, duplicate, dependent)?;
+                    println!(
+                        "      {}",
+                        fix.describe(&duplicate.crate_name, duplicate.newest())
+                    );
+                    fixes.push(fix);
+                }
+            }
+        }
+
+        let mut committed = false;
+        if dedupe_args.apply {
+            committed = apply_fixes(dir, &fixes)?;
+        }
+        reports.push(LockfileDuplicates {
+            dir: dir.clone(),
+            duplicates,
+            committed,
+        });
+    }
+
+    if reports.is_empty() {
+        println!("No lockfiles have more than one version of a crate!");
+    }
+    if let Some(branch) = branch {
+        let any_commits = reports.iter().any(|report| report.committed);
+        branch.finish(false, false, any_commits)?;
+    }
+    Ok(reports)
+}
+
+/// Find every registry crate that the lockfile in `dir` has more than one version of,
+/// along with what depends on each version.
+pub fn find_duplicates(dir: &Path) -> anyhow::Result<Vec<Duplicate>> {
+    let metadata = cargo::metadata(dir, false)
+        .with_context(|| format!("Failed to resolve dependencies in {dir:?}"))?;
+    Ok(duplicates_in(&metadata))
+}
+
+fn duplicates_in(metadata: &Metadata) -> Vec<Duplicate> {
+    let packages: HashMap<_, _> = metadata
+        .packages
+        .iter()
+        .map(|package| (package.id.as_str(), package))
+        .collect();
+
+    let mut versions: BTreeMap<&str, BTreeSet<&Version>> = BTreeMap::new();
+    for package in &metadata.packages {
+        if package.is_from_registry() {
+            versions
+                .entry(&package.name)
+                .or_default()
+                .insert(&package.version);
+        }
+    }
+    versions.retain(|_, versions| versions.len() > 1);
+
+    let mut duplicates: BTreeMap<&str, Duplicate> = versions
+        .iter()
+        .map(|(crate_name, versions)| {
+            let dependents = versions
+                .iter()
+                .map(|version| ((*version).clone(), Vec::new()))
+                .collect();
+            (
+                *crate_name,
+                Duplicate {
+                    crate_name: crate_name.to_string(),
+                    dependents,
+                },
+            )
+        })
+        .collect();
+
+    let nodes = metadata
+        .resolve
+        .as_ref()
+        .map(|resolve| &resolve.nodes[..])
+        .unwrap_or_default();
+    for node in nodes {
+        let Some(dependent) = packages.get(node.id.as_str()) else {
+            continue;
+        };
+        for dep in &node.deps {
+            let Some(package) = packages.get(dep.pkg.as_str()) else {
+                continue;
+            };
+            let Some(duplicate) = duplicates.get_mut(package.name.as_str()) else {
+                continue;
+            };
+            if !package.is_from_registry() {
+                continue;
+            }
+            let dependents = duplicate
+                .dependents
+                .get_mut(&package.version)
+                .expect("every version of a duplicate is listed");
+            dependents.push(Dependent {
+                package: PackageVersion {
+                    name: dependent.name.clone(),
+                    version: dependent.version.clone(),
+                },
+                is_local: dependent.source.is_none(),
+                is_from_crates_io: dependent.is_from_crates_io(),
+            });
+        }
+    }
+
+    let mut duplicates: Vec<_> = duplicates.into_values().collect();
+    for duplicate in &mut duplicates {
+        for dependents in duplicate.dependents.values_mut() {
+            dependents.sort_by(|a, b| a.package.cmp(&b.package));
+        }
+    }
+    duplicates
+}
+
+/// Work out how to get `dependent` onto the newest version of the duplicated crate.
+pub fn suggest_fix(
+    index: &Index,
+    duplicate: &Duplicate,
+    dependent: &Dependent,
+) -> anyhow::Result<Fix> {
+    let package = &dependent.package;
+    if dependent.is_local {
+        return Ok(Fix::UpgradeRequirement {
+            dependent: package.clone(),
+        });
+    }
+    // Git dependencies and other registries' crates aren't in the index,
+    // so there's no telling whether they have a better release.
+    if !dependent.is_from_crates_io {
+        return Ok(Fix::Stuck {
+            dependent: package.clone(),
+        });
+    }
+    let newest = duplicate.newest();
+    let to = index.newest_release(&package.name, |entry| {
+        entry.vers > package.version
+            && cargo::is_semver_compatible(&entry.vers, &package.version)
+            && entry.deps.iter().any(|dep| {
+                !dep.is_dev()
+                    && dep.package_name() == duplicate.crate_name
+                    && dep.req.matches(newest)
+            })
+    })?;
+    Ok(match to {
+        Some(to) => Fix::UpdatePrecise {
+            dependent: package.clone(),
+            to,
+        },
+        None => Fix::Stuck {
+            dependent: package.clone(),
+        },
+    })
+}
+
+/// Apply the fixes that we can in the lockfile in `dir`, and commit the result.
+///
+/// Returns whether anything was committed.
+fn apply_fixes(dir: &Path, fixes: &[Fix]) -> anyhow::Result<bool> {
+    let mut applied = Vec::new();
+    for fix in fixes {
+        let Fix::UpdatePrecise { dependent, to } = fix else {
+            continue;
+        };
+        // Several versions might be fixed by the same update.
+        if applied.contains(&(dependent, to)) {
+            continue;
+        }
+        println!("  Updating {dependent} to {to} in {dir:?}...");
+        if let Err(err) = cargo::update_precise(dir, &dependent.spec(), to) {
+            eprintln!("Warning: couldn't update {dependent} to {to}: {err:#}");
+            continue;
+        }
+        applied.push((dependent, to));
+    }
+
+    if git::is_working_tree_clean()? {
+        println!("  Nothing to apply in {dir:?}.");
+        return Ok(false);
+    }
+
+    let remaining = find_duplicates(dir)?;
+    let mut message = format!("Deduplicate crates in {dir:?}\n\nThese were updated so that fewer versions of their dependencies are needed:\n\n");
+    for (dependent, to) in &applied {
+        message += &format!("- {dependent} -> {to}\n");
+    }
+    if !remaining.is_empty() {
+        message += "\nThese still have more than one version:\n\n";
+        for duplicate in &remaining {
+            let versions: Vec<_> = duplicate
+                .dependents
+                .keys()
+                .map(Version::to_string)
+                .collect();
+            message += &format!("- {} {}\n", duplicate.crate_name, versions.join(", "));
+        }
+    }
+    message += "\nThis commit was created by `cargo-lockstep`.";
+    git::commit_paths(&message, &[&dir.join("Cargo.lock")]).context("Failed to commit changes")?;
+    Ok(true)
+}
@@ -0,0 +1,187 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+use crate::{
+    cargo::{self, Dependency},
+    exclude::ExcludePaths,
+    git,
+};
+
+/// Everything Cargo-related that we found in the repo.
+pub struct Repo {
+    pub workspaces: Vec<Workspace>,
+}
+
+/// A Cargo workspace (or a single package that isn't part of a workspace,
+/// which Cargo treats as a workspace of one).
+pub struct Workspace {
+    /// Directory containing the root manifest, relative to the current directory.
+    pub root: PathBuf,
+    /// Whether there's a "Cargo.lock" file next to the root manifest.
+    pub has_lockfile: bool,
+    /// Whether the lockfile is excluded, even if some of its packages aren't.
+    pub lockfile_excluded: bool,
+    pub projects: Vec<Project>,
+}
+
+/// A single package.
+pub struct Project {
+    pub name: String,
+    /// Directory containing the manifest, relative to the current directory.
+    pub dir: PathBuf,
+    pub dependencies: Vec<Dependency>,
+    /// Feature table, by feature name.
+    pub features: BTreeMap<String, Vec<String>>,
+}
+
+impl Repo {
+    /// Find every Cargo workspace and package under the current directory.
+    ///
+    /// Files ignored by Git are skipped, as are Cargo's `target` directories
+    /// and vendored crates.
+    pub fn discover(exclude_paths: &ExcludePaths) -> anyhow::Result<Self> {
+        let cwd = std::env::current_dir()
+            .and_then(|cwd| cwd.canonicalize())
+            .context("Failed to get current directory")?;
+
+        let mut manifest_dirs = BTreeSet::new();
+        let mut lockfile_dirs = HashSet::new();
+        for path in git::list_files(Path::new(".")).context("Failed to list files in repository")? {
+            let Some(file_name) = path.file_name() else {
+                continue;
+            };
+            if file_name != "Cargo.toml" && file_name != "Cargo.lock" {
+                continue;
+            }
+            let dir = relative_dir(path.parent().unwrap_or(Path::new("")));
+            if is_in_target_or_vendored_dir(&dir) {
+                continue;
+            }
+            if file_name == "Cargo.toml" {
+                manifest_dirs.insert(dir);
+            } else {
+                lockfile_dirs.insert(dir);
+            }
+        }
+
+        // Shallowest first, so that we find workspace roots before their members.
+        let mut manifest_dirs: Vec<_> = manifest_dirs.into_iter().collect();
+        manifest_dirs.sort_by_key(|dir| dir.components().count());
+
+        let mut workspaces = BTreeMap::new();
+        let mut seen_manifests = HashSet::new();
+        for dir in manifest_dirs {
+            let manifest_path = dir.join("Cargo.toml");
+            if seen_manifests.contains(&absolute(&cwd, &manifest_path)) {
+                continue;
+            }
+            if exclude_paths.is_excluded(&manifest_path)? {
+                println!("  Skipping {dir:?} because it matches an excluded path.");
+                continue;
+            }
+
+            let metadata = match cargo::metadata(&dir, true) {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    eprintln!("Warning: failed to read metadata in {dir:?}; skipping: {err:#}");
+                    continue;
+                }
+            };
+
+            let root = relative_to(&cwd, &metadata.workspace_root);
+            let mut projects = Vec::new();
+            for package in metadata.packages {
+                if !seen_manifests.insert(absolute(&cwd, &package.manifest_path)) {
+                    continue;
+                }
+                if exclude_paths.is_excluded(&package.manifest_path)? {
+                    continue;
+                }
+                let project_dir = relative_dir(&relative_to(
+                    &cwd,
+                    package
+                        .manifest_path
+                        .parent()
+                        .context("Manifest path didn't have a parent directory")?,
+                ));
+                projects.push(Project {
+                    name: package.name,
+                    dir: project_dir,
+                    dependencies: package.dependencies,
+                    features: package.features,
+                });
+            }
+
+            // Virtual manifests aren't a package, but we've still covered them.
+            seen_manifests.insert(absolute(&cwd, &root.join("Cargo.toml")));
+            let root = relative_dir(&root);
+            let has_lockfile = lockfile_dirs.contains(&root);
+            let lockfile_excluded =
+                has_lockfile && exclude_paths.is_excluded(&root.join("Cargo.lock"))?;
+            workspaces.insert(
+                root.clone(),
+                Workspace {
+                    root,
+                    has_lockfile,
+                    lockfile_excluded,
+                    projects,
+                },
+            );
+        }
+
+        Ok(Self {
+            workspaces: workspaces.into_values().collect(),
+        })
+    }
+
+    /// Workspaces with lockfiles that we're allowed to touch.
+    pub fn lockfile_workspaces(&self) -> impl Iterator<Item = &Workspace> {
+        self.workspaces
+            .iter()
+            .filter(|workspace| workspace.has_lockfile && !workspace.lockfile_excluded)
+    }
+
+    pub fn projects(&self) -> impl Iterator<Item = &Project> {
+        self.workspaces
+            .iter()
+            .flat_map(|workspace| &workspace.projects)
+    }
+}
+
+/// Make directories look the same as they would when walking from ".",
+/// i.e. "." for the current directory and "./foo" for everything else.
+fn relative_dir(dir: &Path) -> PathBuf {
+    if dir.as_os_str().is_empty() || dir == Path::new(".") {
+        PathBuf::from(".")
+    } else if dir.starts_with(".") {
+        dir.to_owned()
+    } else {
+        Path::new(".").join(dir)
+    }
+}
+
+fn relative_to(cwd: &Path, path: &Path) -> PathBuf {
+    let canonical = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+    canonical
+        .strip_prefix(cwd)
+        .map(Path::to_owned)
+        .unwrap_or(canonical)
+}
+
+fn absolute(cwd: &Path, path: &Path) -> PathBuf {
+    cwd.join(path)
+        .canonicalize()
+        .unwrap_or_else(|_| cwd.join(path))
+}
+
+/// Cargo marks its output directories with a "CACHEDIR.TAG" file,
+/// and `cargo vendor` leaves a ".cargo-checksum.json" in each vendored crate.
+fn is_in_target_or_vendored_dir(dir: &Path) -> bool {
+    dir.ancestors().any(|ancestor| {
+        ancestor.join("CACHEDIR.TAG").exists() || ancestor.join(".cargo-checksum.json").exists()
+    })
+}
@@ -0,0 +1,124 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+use crate::git;
+
+#[derive(clap::Args, Debug)]
+pub struct ExcludeArgs {
+    /// Exclude projects by their "Cargo.toml" or "Cargo.lock" files, or containing directories.
+    ///
+    /// Either a path relative to the current working directory, or a
+    /// gitignore-style pattern relative to the repository root
+    /// (e.g. `examples/**` or `**/fuzz`). Start a pattern with `!` to include
+    /// something that an earlier `--exclude` left out. Every argument must match something.
+    #[arg(long)]
+    pub exclude: Vec<String>,
+}
+
+/// Paths and patterns given via `--exclude`, with gitignore semantics
+/// relative to the repository root.
+///
+/// Arguments that name an existing path (relative to the current directory)
+/// exclude exactly that path. Anything else is treated as a gitignore-style pattern,
+/// e.g. `examples/**` or `**/fuzz`, and a leading `!` re-includes paths
+/// excluded by an earlier pattern.
+pub struct ExcludePaths {
+    root: PathBuf,
+    matcher: Gitignore,
+}
+
+impl ExcludePaths {
+    pub fn from_args(exclude_args: &ExcludeArgs) -> anyhow::Result<Self> {
+        let excludes = &exclude_args.exclude;
+        if excludes.is_empty() {
+            return Ok(Self {
+                root: PathBuf::new(),
+                matcher: Gitignore::empty(),
+            });
+        }
+
+        let root = git::repo_root()
+            .and_then(|root| Ok(root.canonicalize()?))
+            .context("Failed to find repository root for exclude patterns")?;
+        let cwd = std::env::current_dir()
+            .and_then(|cwd| cwd.canonicalize())
+            .context("Failed to get current directory")?;
+        let files = git::list_files(&root).context("Failed to list files in repository")?;
+
+        let mut builder = GitignoreBuilder::new(&root);
+        for exclude in excludes {
+            let line = to_gitignore_line(exclude, &cwd, &root)?;
+
+            // Validate that all exclude rules match something.
+            // (It's bad to let people think that their arguments are doing something if they're not!)
+            let positive = line.strip_prefix('!').unwrap_or(&line);
+            let mut single = GitignoreBuilder::new(&root);
+            single
+                .add_line(None, positive)
+                .with_context(|| format!("Invalid exclude pattern {exclude:?}"))?;
+            let single = single
+                .build()
+                .with_context(|| format!("Invalid exclude pattern {exclude:?}"))?;
+            if !files
+                .iter()
+                .any(|file| single.matched_path_or_any_parents(file, false).is_ignore())
+            {
+                anyhow::bail!("Exclude pattern {exclude:?} doesn't match anything!");
+            }
+
+            builder
+                .add_line(None, &line)
+                .with_context(|| format!("Invalid exclude pattern {exclude:?}"))?;
+        }
+        let matcher = builder
+            .build()
+            .context("Failed to build exclude patterns")?;
+        Ok(Self { root, matcher })
+    }
+
+    /// Whether the given path (which must exist) is excluded, either directly
+    /// or because it's inside an excluded directory.
+    pub fn is_excluded(&self, path: &Path) -> anyhow::Result<bool> {
+        if self.matcher.is_empty() {
+            return Ok(false);
+        }
+        let absolute_path = path
+            .canonicalize()
+            .with_context(|| format!("Failed to canonicalize path {path:?}"))?;
+        let Ok(relative_path) = absolute_path.strip_prefix(&self.root) else {
+            // Outside the repo, so no pattern can apply.
+            return Ok(false);
+        };
+        Ok(self
+            .matcher
+            .matched_path_or_any_parents(relative_path, absolute_path.is_dir())
+            .is_ignore())
+    }
+}
+
+/// Turn an `--exclude` argument into a line for a gitignore file at the repository root.
+fn to_gitignore_line(exclude: &str, cwd: &Path, root: &Path) -> anyhow::Result<String> {
+    let looks_like_pattern = exclude.starts_with('!') || exclude.contains(['*', '?', '[']);
+    if looks_like_pattern || !Path::new(exclude).exists() {
+        return Ok(exclude.to_string());
+    }
+
+    // Existing paths keep meaning what they always did: that path, relative to
+    // where we're running, so anchor them to the root.
+    let absolute_path = cwd
+        .join(exclude)
+        .canonicalize()
+        .with_context(|| format!("Failed to canonicalize exclude path {exclude:?}"))?;
+    let relative_path = absolute_path
+        .strip_prefix(root)
+        .with_context(|| format!("Excluded path {exclude:?} is outside the repository"))?;
+    if relative_path.as_os_str().is_empty() {
+        return Ok("*".to_string());
+    }
+    let relative_path = relative_path
+        .to_str()
+        .with_context(|| format!("Excluded path {exclude:?} isn't valid UTF-8"))?;
+    Ok(format!("/{relative_path}"))
+}
@@ -0,0 +1,216 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use anyhow::Context;
+
+use crate::{
+    cargo,
+    check::CheckResult,
+    discovery::{Project, Workspace},
+    git,
+};
+
+/// Features to `cargo check` a workspace with.
+#[derive(Clone, PartialEq, Eq)]
+pub enum FeatureCombination {
+    AllFeatures,
+    NoDefaultFeatures,
+    /// A single feature of a single package, on top of its defaults.
+    Feature {
+        package: String,
+        feature: String,
+    },
+}
+
+impl FeatureCombination {
+    fn args(&self) -> Vec<String> {
+        match self {
+            Self::AllFeatures => vec!["--all-features".to_string()],
+            Self::NoDefaultFeatures => vec!["--no-default-features".to_string()],
+            Self::Feature { package, feature } => vec![
+                "--package".to_string(),
+                package.clone(),
+                "--features".to_string(),
+                feature.clone(),
+            ],
+        }
+    }
+}
+
+impl fmt::Display for FeatureCombination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AllFeatures => write!(f, "--all-features"),
+            Self::NoDefaultFeatures => write!(f, "--no-default-features"),
+            Self::Feature { package, feature } => write!(f, "{package}/{feature}"),
+        }
+    }
+}
+
+/// A feature combination that doesn't build.
+pub struct FeatureFailure {
+    pub dir: PathBuf,
+    pub combination: FeatureCombination,
+    pub error: anyhow::Error,
+}
+
+impl fmt::Display for FeatureFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} in {:?}", self.combination, self.dir)
+    }
+}
+
+/// What checking a workspace's feature combinations found.
+#[derive(Default)]
+pub struct FeatureCheckOutcome {
+    pub passed: Vec<CheckResult>,
+    /// Combinations that fail now but didn't before the upgrade.
+    pub regressed: Vec<FeatureFailure>,
+    /// Combinations that were already failing before the upgrade.
+    pub already_failing: Vec<FeatureFailure>,
+}
+
+/// The feature combinations worth checking after upgrading `crate_names`:
+/// everything on, everything off, and each feature that pulls in one of them.
+///
+/// There's nothing worth checking in workspaces that don't depend on any of them.
+pub fn combinations(workspace: &Workspace, crate_names: &[String]) -> Vec<FeatureCombination> {
+    let depends_on_any = workspace.projects.iter().any(|project| {
+        project
+            .dependencies
+            .iter()
+            .any(|dep| crate_names.contains(&dep.name))
+    });
+    if !depends_on_any {
+        return Vec::new();
+    }
+    let mut combinations = vec![
+        FeatureCombination::AllFeatures,
+        FeatureCombination::NoDefaultFeatures,
+    ];
+    for project in &workspace.projects {
+        for feature in features_enabling(project, crate_names) {
+            combinations.push(FeatureCombination::Feature {
+                package: project.name.clone(),
+                feature,
+            });
+        }
+    }
+    combinations
+}
+
+/// Features of `project` that enable any of `crate_names`, directly or via other features.
+pub fn features_enabling(project: &Project, crate_names: &[String]) -> BTreeSet<String> {
+    // Features refer to dependencies by the name the package uses for them.
+    let dep_names: HashSet<_> = project
+        .dependencies
+        .iter()
+        .filter(|dep| crate_names.contains(&dep.name))
+        .map(|dep| dep.rename.as_deref().unwrap_or(&dep.name))
+        .collect();
+
+    project
+        .features
+        .keys()
+        .filter(|feature| {
+            let mut seen = HashSet::new();
+            enables_any(project, feature, &dep_names, &mut seen)
+        })
+        .cloned()
+        .collect()
+}
+
+fn enables_any<'a>(
+    project: &'a Project,
+    feature: &'a str,
+    dep_names: &HashSet<&str>,
+    seen: &mut HashSet<&'a str>,
+) -> bool {
+    if !seen.insert(feature) {
+        return false;
+    }
+    let Some(values) = project.features.get(feature) else {
+        return false;
+    };
+    values.iter().any(|value| {
+        // Values are `dep:name`, `name/feature`, `name?/feature`, or another feature.
+        let (name, _) = value.split_once('/').unwrap_or((value, ""));
+        let name = name.strip_suffix('?').unwrap_or(name);
+        if let Some(dep_name) = name.strip_prefix("dep:") {
+            return dep_names.contains(dep_name);
+        }
+        dep_names.contains(name) || enables_any(project, name, dep_names, seen)
+    })
+}
+
+/// Check every combination in `dir`. If any fail, check them again without
+/// the uncommitted changes to find out whether the upgrade is to blame.
+///
+/// The working tree is left as it was.
+pub fn check_combinations(
+    dir: &Path,
+    combinations: &[FeatureCombination],
+) -> anyhow::Result<FeatureCheckOutcome> {
+    let mut outcome = FeatureCheckOutcome::default();
+    let mut failed = Vec::new();
+    for combination in combinations {
+        println!("  Running `cargo check --all-targets` with {combination} in {dir:?}...");
+        let started = Instant::now();
+        match cargo::check_with_args(dir, &combination.args()) {
+            Ok(()) => outcome.passed.push(CheckResult {
+                run: format!("cargo check --all-targets {}", combination.args().join(" ")),
+                dir: dir.to_owned(),
+                duration: started.elapsed(),
+            }),
+            Err(error) => {
+                println!("    Failed!");
+                failed.push(FeatureFailure {
+                    dir: dir.to_owned(),
+                    combination: combination.clone(),
+                    error,
+                });
+            }
+        }
+    }
+    if failed.is_empty() {
+        return Ok(outcome);
+    }
+
+    println!("  Checking whether those failed before upgrading...");
+    // If there's nothing to set aside, the tree is already how it was before upgrading.
+    let stash =
+        git::stash().context("Failed to set aside upgrade to check for earlier failures")?;
+    let before: Vec<_> = failed
+        .iter()
+        .map(|failure| cargo::check_with_args(dir, &failure.combination.args()).is_ok())
+        .collect();
+    if let Some(stash) = stash {
+        git::unstash(&stash)
+            .context("Failed to restore upgrade after checking for earlier failures")?;
+    }
+
+    for (failure, passed_before) in failed.into_iter().zip(before) {
+        if passed_before {
+            outcome.regressed.push(failure);
+        } else {
+            outcome.already_failing.push(failure);
+        }
+    }
+    Ok(outcome)
+}
+
+pub fn commit_message_section(already_failing: &[FeatureFailure]) -> String {
+    if already_failing.is_empty() {
+        return String::new();
+    }
+    let mut section =
+        "\nThese feature combinations were already failing before this upgrade:\n\n".to_string();
+    for failure in already_failing {
+        section += &format!("- {failure}\n");
+    }
+    section
+}
@@ -1,10 +1,15 @@
-use std::process::Command;
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
 
-use crate::command_ext::CommandExt;
+use anyhow::Context;
+
+use crate::{command_ext::CommandExt, runner::Invocation};
 
 pub fn is_working_tree_clean() -> anyhow::Result<bool> {
     let mut cmd = Command::new("git");
-    cmd.args(["diff", "--exit-code"]).null_io();
+    cmd.args(["diff", "--quiet"]);
     let exit_code = cmd.clean_exit_code()?;
     if exit_code == 0 {
         // Working tree is clean.
@@ -13,7 +18,10 @@ pub fn is_working_tree_clean() -> anyhow::Result<bool> {
         // There are changes in the working tree.
         Ok(false)
     } else {
-        anyhow::bail!("Unrecognised exit code {exit_code} from `{cmd:?}`")
+        anyhow::bail!(
+            "Unrecognised exit code {exit_code} from `{}`",
+            Invocation::of(&cmd)
+        )
     }
 }
 
@@ -36,28 +44,262 @@ pub fn guess_base_branch() -> anyhow::Result<String> {
 
 pub fn fetch(branch_name: &str) -> anyhow::Result<()> {
     let mut cmd = Command::new("git");
-    cmd.args(["fetch", "origin", branch_name]).null_io();
+    cmd.args(["fetch", "origin", branch_name]);
+    cmd.success_or_err()
+}
+
+/// Fetch a branch from origin, unless it doesn't exist there.
+///
+/// Returns whether it existed.
+pub fn fetch_if_exists(branch_name: &str) -> anyhow::Result<bool> {
+    let mut cmd = Command::new("git");
+    cmd.args(["fetch", "origin", branch_name]);
+    let output = cmd.clean_output()?;
+    if output.status.success() {
+        return Ok(true);
+    }
+    if String::from_utf8_lossy(&output.stderr).contains("couldn't find remote ref") {
+        return Ok(false);
+    }
+    Err(cmd.failure(&output))
+}
+
+/// Look up what a branch points to on origin, without fetching it.
+pub fn remote_branch_commit(branch_name: &str) -> anyhow::Result<Option<String>> {
+    let mut cmd = Command::new("git");
+    cmd.args(["ls-remote", "--heads", "origin"])
+        .arg(format!("refs/heads/{branch_name}"));
+    let output = cmd.output_if_success_else_err()?;
+    let stdout = String::from_utf8(output.stdout).context("Remote refs weren't valid UTF-8")?;
+    Ok(stdout
+        .split_whitespace()
+        .next()
+        .map(|commit| commit.to_string()))
+}
+
+/// Push a branch to origin and set it as the upstream.
+///
+/// With `expected_remote_commit`, overwrite the branch on origin as long as
+/// it's still where we expect; otherwise only push if that's a fast-forward.
+pub fn push(branch_name: &str, expected_remote_commit: Option<&str>) -> anyhow::Result<()> {
+    let mut cmd = Command::new("git");
+    cmd.args(["push", "--set-upstream"]);
+    if let Some(expected_remote_commit) = expected_remote_commit {
+        cmd.arg(format!(
+            "--force-with-lease=refs/heads/{branch_name}:{expected_remote_commit}"
+        ));
+    }
+    cmd.args(["origin", branch_name]);
+    cmd.success_or_err()
+}
+
+/// Look up the commit a revision points to, if it exists.
+pub fn resolve_commit(revision: &str) -> anyhow::Result<Option<String>> {
+    let mut cmd = Command::new("git");
+    cmd.args(["rev-parse", "--verify", "--quiet"])
+        .arg(format!("{revision}^{{commit}}"));
+    let output = cmd.clean_output()?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let stdout = String::from_utf8(output.stdout).context("Commit hash wasn't valid UTF-8")?;
+    Ok(Some(stdout.trim_end().to_string()))
+}
+
+/// Messages of the commits on a local branch that aren't on any branch on origin.
+pub fn unpushed_commit_messages(branch_name: &str) -> anyhow::Result<Vec<String>> {
+    let mut cmd = Command::new("git");
+    cmd.args(["log", "--format=%B%x00"])
+        .arg(format!("refs/heads/{branch_name}"))
+        .args(["--not", "--remotes=origin"]);
+    let output = cmd.output_if_success_else_err()?;
+    let stdout = String::from_utf8(output.stdout).context("Commit messages weren't valid UTF-8")?;
+    Ok(stdout
+        .split('\0')
+        .map(str::trim)
+        .filter(|message| !message.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Whether two commits have exactly the same files, regardless of how they got there.
+pub fn same_tree(a: &str, b: &str) -> anyhow::Result<bool> {
+    let mut cmd = Command::new("git");
+    cmd.args(["diff", "--quiet", a, b]);
+    match cmd.clean_exit_code()? {
+        0 => Ok(true),
+        1 => Ok(false),
+        exit_code => anyhow::bail!(
+            "Unrecognised exit code {exit_code} from `{}`",
+            Invocation::of(&cmd)
+        ),
+    }
+}
+
+/// Switch to a branch starting at `start_point`, creating it or throwing away
+/// whatever was on it before.
+pub fn reset_branch(branch_name: &str, start_point: &str) -> anyhow::Result<()> {
+    let mut cmd = Command::new("git");
+    cmd.args(["checkout", "-B", branch_name, start_point]);
     cmd.success_or_err()
 }
 
 pub fn switch_to_new_branch(new_branch_name: &str, start_point: &str) -> anyhow::Result<()> {
     let mut cmd = Command::new("git");
-    cmd.args(["checkout", "-b", new_branch_name, start_point])
-        .null_io();
+    cmd.args(["checkout", "-b", new_branch_name, start_point]);
+    cmd.success_or_err()
+}
+
+/// Switch to a branch that already exists.
+pub fn switch_to_branch(branch_name: &str) -> anyhow::Result<()> {
+    let mut cmd = Command::new("git");
+    cmd.args(["checkout", branch_name]);
     cmd.success_or_err()
 }
 
+pub fn current_branch() -> anyhow::Result<String> {
+    let mut cmd = Command::new("git");
+    cmd.args(["branch", "--show-current"]);
+    let output = cmd.output_if_success_else_err()?;
+    let stdout = String::from_utf8(output.stdout).context("Branch name wasn't valid UTF-8")?;
+    Ok(stdout.trim_end().to_string())
+}
+
 pub fn commit(message: &str) -> anyhow::Result<()> {
     let mut cmd = Command::new("git");
     // TODO: It would be safer to not pass '-a' here;
     // I'd prefer to add exactly what we intend to commit
     // and then blow up if there was anything else not staged.
-    cmd.args(["commit", "-a", "-m", message]).null_io();
+    cmd.args(["commit", "-a", "-m", message]);
+    cmd.success_or_err()
+}
+
+/// Commit only the given paths, regardless of what else has changed.
+pub fn commit_paths(message: &str, paths: &[&Path]) -> anyhow::Result<()> {
+    let mut cmd = Command::new("git");
+    cmd.args(["commit", "-m", message, "--"]).args(paths);
+    cmd.success_or_err()
+}
+
+/// Throw away uncommitted changes to the given path.
+pub fn discard_path_changes(path: &Path) -> anyhow::Result<()> {
+    let mut cmd = Command::new("git");
+    cmd.args(["checkout", "--"]).arg(path);
+    cmd.success_or_err()
+}
+
+/// Throw away all uncommitted changes to tracked files.
+pub fn discard_changes() -> anyhow::Result<()> {
+    let mut cmd = Command::new("git");
+    cmd.args(["checkout", "--", "."]);
+    cmd.success_or_err()
+}
+
+/// Put uncommitted changes to tracked files aside, leaving the working tree as it is at `HEAD`.
+///
+/// Returns the stash that was made, or `None` if there was nothing to put aside
+/// (in which case Git doesn't make one).
+pub fn stash() -> anyhow::Result<Option<String>> {
+    let before = latest_stash()?;
+    let mut cmd = Command::new("git");
+    cmd.args(["stash", "push", "--quiet"]);
+    cmd.success_or_err()?;
+    let after = latest_stash()?;
+    Ok(if after == before { None } else { after })
+}
+
+/// Bring back the changes from a [`stash`], throwing away anything done since.
+///
+/// Refuses to touch any other stash, e.g. one of the user's.
+pub fn unstash(stash: &str) -> anyhow::Result<()> {
+    if latest_stash()?.as_deref() != Some(stash) {
+        anyhow::bail!(
+            "Stash {stash} is no longer the latest; restore it with `git stash apply {stash}`"
+        );
+    }
+    discard_changes()?;
+    let mut cmd = Command::new("git");
+    cmd.args(["stash", "pop", "--quiet"]);
     cmd.success_or_err()
 }
 
+/// The commit for the most recent stash, if there are any.
+fn latest_stash() -> anyhow::Result<Option<String>> {
+    let mut cmd = Command::new("git");
+    cmd.args(["rev-parse", "--quiet", "--verify", "refs/stash"]);
+    let output = cmd.clean_output()?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let stdout = String::from_utf8(output.stdout).context("Stash commit wasn't valid UTF-8")?;
+    Ok(Some(stdout.trim().to_string()))
+}
+
+/// Find the ".git" directory (or wherever Git is keeping things) for the repository we're in.
+pub fn git_dir() -> anyhow::Result<PathBuf> {
+    let mut cmd = Command::new("git");
+    cmd.args(["rev-parse", "--absolute-git-dir"]);
+    let output = cmd.output_if_success_else_err()?;
+    let stdout = String::from_utf8(output.stdout).context("Git directory wasn't valid UTF-8")?;
+    Ok(PathBuf::from(stdout.trim_end()))
+}
+
+/// Find the top level of the repository we're in.
+pub fn repo_root() -> anyhow::Result<PathBuf> {
+    let mut cmd = Command::new("git");
+    cmd.args(["rev-parse", "--show-toplevel"]);
+    let output = cmd.output_if_success_else_err()?;
+    let stdout = String::from_utf8(output.stdout).context("Repository path wasn't valid UTF-8")?;
+    Ok(PathBuf::from(stdout.trim_end()))
+}
+
+/// List all files under `directory` that Git knows about
+/// or would consider adding, i.e. everything that isn't ignored.
+///
+/// Paths are relative to `directory`.
+pub fn list_files(directory: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut cmd = Command::new("git");
+    cmd.args([
+        "ls-files",
+        "-z",
+        "--cached",
+        "--others",
+        "--exclude-standard",
+    ])
+    .current_dir(directory);
+    let output = cmd.output_if_success_else_err()?;
+    let stdout = String::from_utf8(output.stdout).context("File list wasn't valid UTF-8")?;
+    Ok(stdout
+        .split('\0')
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        // Tracked files that have been deleted are still listed.
+        .filter(|path| directory.join(path).exists())
+        .collect())
+}
+
+/// Read a file as it is in `HEAD`, or `None` if it isn't tracked there.
+pub fn show_at_head(path: &Path) -> anyhow::Result<Option<String>> {
+    // A "./" prefix makes Git interpret the path relative to the current directory
+    // rather than the repository root.
+    let relative = if path.starts_with(".") {
+        path.to_owned()
+    } else {
+        Path::new(".").join(path)
+    };
+    let mut cmd = Command::new("git");
+    cmd.arg("show").arg(format!("HEAD:{}", relative.display()));
+    let output = cmd.clean_output()?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    Ok(Some(
+        String::from_utf8(output.stdout).context("File in Git wasn't valid UTF-8")?,
+    ))
+}
+
 fn branch_exists(branch_name: &str) -> anyhow::Result<bool> {
     let mut cmd = Command::new("git");
-    cmd.args(["show-branch", branch_name]).null_io();
+    cmd.args(["show-branch", branch_name]);
     Ok(cmd.clean_exit_status()?.success())
 }
@@ -0,0 +1,159 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Context;
+use semver::Version;
+
+use crate::{config::Config, cooldown::Cooldown, index::Index};
+
+/// A set of crates that must always be upgraded together.
+pub struct Group {
+    pub name: String,
+    /// Crate names, or prefixes ending with `*`.
+    pub members: Vec<String>,
+}
+
+impl Group {
+    /// Resolve a `--group` argument, which is either the name of a group
+    /// from the config file, or an ad-hoc comma-separated list of crates.
+    pub fn from_arg(arg: &str, config: &Config) -> anyhow::Result<Self> {
+        if let Some(members) = config.groups.get(arg) {
+            if members.is_empty() {
+                anyhow::bail!("Group {arg:?} doesn't have any members");
+            }
+            return Ok(Self {
+                name: arg.to_string(),
+                members: members.clone(),
+            });
+        }
+        if arg.contains(',') {
+            return Ok(Self {
+                name: arg.to_string(),
+                members: arg
+                    .split(',')
+                    .map(|member| member.trim().to_string())
+                    .filter(|member| !member.is_empty())
+                    .collect(),
+            });
+        }
+        let known: Vec<_> = config.groups.keys().map(String::as_str).collect();
+        anyhow::bail!(
+            "Unknown group {arg:?}; groups in {:?} are: {known:?}",
+            crate::config::CONFIG_FILE_NAME
+        )
+    }
+
+    pub fn has_wildcards(&self) -> bool {
+        self.members.iter().any(|member| member.ends_with('*'))
+    }
+
+    /// Expand wildcard members against the names of dependencies actually used in the repo.
+    ///
+    /// Fails if any member doesn't match anything, because that probably means
+    /// the group definition is wrong.
+    pub fn expand(&self, known_dependencies: &BTreeSet<String>) -> anyhow::Result<Vec<String>> {
+        let mut expanded = BTreeSet::new();
+        for member in &self.members {
+            match member.strip_suffix('*') {
+                Some(prefix) => {
+                    let matches: Vec<_> = known_dependencies
+                        .iter()
+                        .filter(|name| name.starts_with(prefix))
+                        .cloned()
+                        .collect();
+                    if matches.is_empty() {
+                        anyhow::bail!(
+                            "{member:?} in group {:?} doesn't match any dependencies",
+                            self.name
+                        );
+                    }
+                    expanded.extend(matches);
+                }
+                None => {
+                    expanded.insert(member.clone());
+                }
+            }
+        }
+        Ok(expanded.into_iter().collect())
+    }
+}
+
+/// Pick the newest version of each member of a group such that every member's
+/// requirements on the other members are satisfied.
+///
+/// Starts from the newest allowed release of each crate and walks backwards
+/// until everything agrees. This is greedy rather than exhaustive, but it's
+/// good enough for the usual case of a set of crates released together.
+pub fn pick_compatible_versions(
+    index: &Index,
+    cooldown: Option<&Cooldown>,
+    members: &[String],
+) -> anyhow::Result<BTreeMap<String, Version>> {
+    // Newest first.
+    let mut candidates: BTreeMap<String, Vec<Version>> = BTreeMap::new();
+    for member in members {
+        let mut versions: Vec<_> = index
+            .entries(member)
+            .with_context(|| format!("Failed to look up versions of {member:?}"))?
+            .iter()
+            .filter(|entry| entry.vers.pre.is_empty() && !entry.yanked)
+            .filter(|entry| cooldown.is_none_or(|cooldown| !cooldown.is_too_new(entry)))
+            .map(|entry| entry.vers.clone())
+            .collect();
+        if versions.is_empty() {
+            anyhow::bail!("{member:?} doesn't have any releases we can upgrade to");
+        }
+        versions.sort_by(|a, b| b.cmp(a));
+        candidates.insert(member.clone(), versions);
+    }
+
+    let mut chosen: BTreeMap<String, usize> =
+        members.iter().map(|member| (member.clone(), 0)).collect();
+    loop {
+        let mut changed = false;
+        for member in members {
+            let version = &candidates[member][chosen[member]];
+            let deps = index
+                .entry(member, version)?
+                .map(|entry| entry.deps.clone())
+                .unwrap_or_default();
+            for dep in deps.iter().filter(|dep| !dep.is_dev()) {
+                let Some(dep_candidates) = candidates.get(dep.package_name()) else {
+                    // Not in the group.
+                    continue;
+                };
+                let dep_position = chosen[dep.package_name()];
+                if dep.req.matches(&dep_candidates[dep_position]) {
+                    continue;
+                }
+                changed = true;
+                // Prefer going back on the dependency, because that's usually
+                // what lags behind (e.g. `prost` vs `tonic`); otherwise try an older
+                // version of the crate that depends on it.
+                if let Some(offset) = dep_candidates[dep_position..]
+                    .iter()
+                    .position(|candidate| dep.req.matches(candidate))
+                {
+                    *chosen.get_mut(dep.package_name()).unwrap() += offset;
+                } else {
+                    let position = chosen.get_mut(member).unwrap();
+                    *position += 1;
+                    if *position >= candidates[member].len() {
+                        anyhow::bail!("Couldn't find mutually compatible versions of {members:?}");
+                    }
+                }
+                break;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    Ok(chosen
+        .into_iter()
+        .map(|(member, position)| {
+            let version = candidates[&member][position].clone();
+            (member, version)
+        })
+        .collect())
+}
@@ -0,0 +1,230 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    process::Command,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use semver::{Version, VersionReq};
+
+use crate::command_ext::CommandExt as _;
+
+// Where to read registry index files from.
+//
+// Defaults to the crates.io sparse index, but can be pointed at
+// a local directory with the same layout (e.g. a local registry)
+// by setting `CARGO_LOCKSTEP_INDEX`.
+const INDEX_ENV_VAR: &str = "CARGO_LOCKSTEP_INDEX";
+const DEFAULT_SPARSE_INDEX_URL: &str = "https://index.crates.io/";
+
+/// One line from a registry index file, i.e. one published version of a crate.
+///
+/// We only deserialize the bits we care about.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct IndexEntry {
+    pub vers: Version,
+    #[serde(default)]
+    pub yanked: bool,
+    /// When this version was published.
+    ///
+    /// Older index entries (and some alternative registries)
+    /// don't include this.
+    pub pubtime: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub deps: Vec<IndexDependency>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct IndexDependency {
+    /// Name of the dependency as used by the depending crate; see `package_name`.
+    pub name: String,
+    pub req: VersionReq,
+    /// Set if the dependency was renamed.
+    pub package: Option<String>,
+    pub kind: Option<String>,
+}
+
+impl IndexDependency {
+    /// Name of the crate this actually depends on, accounting for renames.
+    pub fn package_name(&self) -> &str {
+        self.package.as_deref().unwrap_or(&self.name)
+    }
+
+    pub fn is_dev(&self) -> bool {
+        self.kind.as_deref() == Some("dev")
+    }
+}
+
+enum IndexSource {
+    Sparse(String),
+    Local(PathBuf),
+}
+
+/// Reads registry index files, caching them for the life of the process
+/// so that we only fetch each crate once per run.
+///
+/// This can be shared between threads.
+pub struct Index {
+    source: IndexSource,
+    /// `None` for crates that aren't in the index at all.
+    cache: Mutex<HashMap<String, Option<Arc<Vec<IndexEntry>>>>>,
+}
+
+impl Index {
+    pub fn from_env() -> Self {
+        let source = match std::env::var(INDEX_ENV_VAR) {
+            Ok(location) => match location.strip_prefix("file://") {
+                Some(path) => IndexSource::Local(PathBuf::from(path)),
+                None if location.starts_with("https://") || location.starts_with("http://") => {
+                    let mut url = location;
+                    if !url.ends_with('/') {
+                        url.push('/');
+                    }
+                    IndexSource::Sparse(url)
+                }
+                None => IndexSource::Local(PathBuf::from(location)),
+            },
+            Err(_) => IndexSource::Sparse(DEFAULT_SPARSE_INDEX_URL.to_string()),
+        };
+        Self {
+            source,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Get every published version of the given crate, in the order they appear in the index.
+    ///
+    /// Fails if there's no such crate.
+    pub fn entries(&self, crate_name: &str) -> anyhow::Result<Arc<Vec<IndexEntry>>> {
+        self.entries_if_known(crate_name)?
+            .ok_or_else(|| unknown_crate(crate_name))
+    }
+
+    /// Like [`Index::entries`], but returns `None` if there's no such crate,
+    /// e.g. because it's from another registry.
+    pub fn entries_if_known(
+        &self,
+        crate_name: &str,
+    ) -> anyhow::Result<Option<Arc<Vec<IndexEntry>>>> {
+        let crate_name = crate_name.to_lowercase();
+        if let Some(entries) = self.cache.lock().unwrap().get(&crate_name) {
+            return Ok(entries.clone());
+        }
+
+        // Don't hold the lock while fetching; at worst two threads fetch the same file,
+        // in which case the first one to finish wins so everyone sees the same entries.
+        let Some(raw) = self
+            .fetch(&crate_name)
+            .with_context(|| format!("Failed to fetch index file for {crate_name:?}"))?
+        else {
+            return Ok(self
+                .cache
+                .lock()
+                .unwrap()
+                .entry(crate_name)
+                .or_insert(None)
+                .clone());
+        };
+        let mut entries = Vec::new();
+        for line in raw.lines().filter(|line| !line.trim().is_empty()) {
+            // Skip entries we can't make sense of (e.g. weird legacy versions)
+            // rather than failing the whole crate.
+            match serde_json::from_str::<IndexEntry>(line) {
+                Ok(entry) => entries.push(entry),
+                Err(err) => {
+                    eprintln!("Warning: skipping unparseable index entry for {crate_name:?}: {err}")
+                }
+            }
+        }
+        Ok(self
+            .cache
+            .lock()
+            .unwrap()
+            .entry(crate_name)
+            .or_insert(Some(Arc::new(entries)))
+            .clone())
+    }
+
+    /// Look up a specific published version of a crate.
+    ///
+    /// Returns `None` if the crate or version isn't in the index.
+    pub fn entry(&self, crate_name: &str, version: &Version) -> anyhow::Result<Option<IndexEntry>> {
+        let Some(entries) = self.entries_if_known(crate_name)? else {
+            return Ok(None);
+        };
+        Ok(entries.iter().find(|entry| entry.vers == *version).cloned())
+    }
+
+    /// Find the newest normal (non-pre-release), non-yanked release of a crate
+    /// that satisfies `predicate`.
+    pub fn newest_release(
+        &self,
+        crate_name: &str,
+        predicate: impl Fn(&IndexEntry) -> bool,
+    ) -> anyhow::Result<Option<Version>> {
+        Ok(self
+            .entries(crate_name)?
+            .iter()
+            .filter(|entry| entry.vers.pre.is_empty() && !entry.yanked)
+            .filter(|entry| predicate(entry))
+            .map(|entry| &entry.vers)
+            .max()
+            .cloned())
+    }
+
+    /// Returns `None` if there's no such crate.
+    fn fetch(&self, crate_name: &str) -> anyhow::Result<Option<String>> {
+        let relative_path = index_path(crate_name);
+        match &self.source {
+            IndexSource::Sparse(base_url) => {
+                let url = format!("{base_url}{relative_path}");
+                let mut cmd = Command::new("curl");
+                cmd.args(["--silent", "--show-error", "--fail", "--location", &url]);
+                let output = cmd.clean_output()?;
+                if !output.status.success() {
+                    // curl exits with 22 for HTTP errors.
+                    if output.status.code() == Some(22)
+                        && String::from_utf8_lossy(&output.stderr).contains("404")
+                    {
+                        return Ok(None);
+                    }
+                    return Err(cmd.failure(&output))
+                        .with_context(|| format!("Failed to download {url:?}"));
+                }
+                String::from_utf8(output.stdout)
+                    .map(Some)
+                    .context("Index file wasn't valid UTF-8")
+            }
+            IndexSource::Local(root) => {
+                let path = root.join(&relative_path);
+                if !path.exists() {
+                    return Ok(None);
+                }
+                std::fs::read_to_string(&path)
+                    .map(Some)
+                    .with_context(|| format!("Failed to read index file {path:?}"))
+            }
+        }
+    }
+}
+
+/// The error for a crate that isn't in the registry, which is usually a typo.
+pub(crate) fn unknown_crate(crate_name: &str) -> anyhow::Error {
+    anyhow::anyhow!(
+        "There's no crate called {crate_name:?} in the registry; is it spelled correctly?"
+    )
+}
+
+/// Path of a crate's file within a registry index, relative to the index root.
+///
+/// See <https://doc.rust-lang.org/cargo/reference/registry-index.html#index-files>.
+fn index_path(crate_name: &str) -> String {
+    match crate_name.len() {
+        1 => format!("1/{crate_name}"),
+        2 => format!("2/{crate_name}"),
+        3 => format!("3/{}/{crate_name}", &crate_name[..1]),
+        _ => format!("{}/{}/{crate_name}", &crate_name[..2], &crate_name[2..4]),
+    }
+}
@@ -0,0 +1,42 @@
+//! Keep dependencies in lockstep across all the Cargo projects in a repo.
+//!
+//! This is what the `cargo-lockstep` binary is built on, for tools that
+//! want to drive it directly rather than parsing its output. The main entry points are:
+//!
+//! - [`discovery::Repo::discover`] to find all the workspaces and projects in a repo.
+//! - [`cargo::get_latest_versions`] and [`index::Index`] to look up releases.
+//! - [`outdated::find_outdated`] to find dependencies with newer incompatible releases.
+//! - [`upgrade::plan_upgrade`] and [`upgrade::apply_batch`] to plan and apply upgrades,
+//!   or [`upgrade::upgrade_one`] to do the whole thing.
+//! - [`update_all::update_all`] to update every lockfile.
+//! - [`check::run_checks`] to verify that a project still works,
+//!   or [`cargo::check`] for just a `cargo check`.
+//!
+//! Every Git and Cargo command goes through [`runner::runner`], which can be
+//! replaced to trace or fake them.
+
+pub mod audit;
+pub mod branch;
+pub mod cargo;
+pub mod check;
+mod command_ext;
+pub mod config;
+pub mod cooldown;
+pub mod dedupe;
+pub mod discovery;
+pub mod exclude;
+pub mod features;
+mod git;
+pub mod group;
+pub mod index;
+pub mod lockfile;
+pub mod lockstep;
+pub mod outdated;
+mod parallel;
+pub mod release_notes;
+pub mod run_state;
+pub mod runner;
+pub mod update_all;
+pub mod upgrade;
+pub mod why;
+pub mod yanked;
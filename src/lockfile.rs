@@ -0,0 +1,245 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fmt,
+    path::Path,
+};
+
+use anyhow::Context;
+use semver::Version;
+
+use crate::{
+    cargo::{self, CRATES_IO_SOURCES},
+    git,
+};
+
+/// The parts of a "Cargo.lock" file that we care about.
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+pub struct Lockfile {
+    #[serde(default, rename = "package")]
+    pub packages: Vec<LockedPackage>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Eq)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: Version,
+    /// Missing for path dependencies and workspace members.
+    pub source: Option<String>,
+    /// What this package depends on, as "name", "name version", or "name version (source)",
+    /// depending on what's needed to be unambiguous.
+    #[serde(default)]
+    pub dependencies: Vec<String>,
+}
+
+/// Packages are the same if they're the same version from the same place,
+/// regardless of how their own dependencies were resolved.
+impl PartialEq for LockedPackage {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.version == other.version && self.source == other.source
+    }
+}
+
+impl LockedPackage {
+    /// Whether this package came from a registry (as opposed to a path or git dependency).
+    pub fn is_from_registry(&self) -> bool {
+        self.source
+            .as_deref()
+            .is_some_and(|source| source.starts_with("registry+") || source.starts_with("sparse+"))
+    }
+
+    /// Whether this package came from crates.io, rather than some other registry.
+    pub fn is_from_crates_io(&self) -> bool {
+        self.source
+            .as_deref()
+            .is_some_and(|source| CRATES_IO_SOURCES.contains(&source))
+    }
+
+    /// Package ID spec that unambiguously identifies this package to `cargo update -p`.
+    pub fn spec(&self) -> String {
+        format!("{}@{}", self.name, self.version)
+    }
+}
+
+impl Lockfile {
+    /// Read the "Cargo.lock" file in the given directory.
+    pub fn read(directory: &Path) -> anyhow::Result<Self> {
+        let path = directory.join("Cargo.lock");
+        let contents =
+            std::fs::read_to_string(&path).with_context(|| format!("Failed to read {path:?}"))?;
+        toml::from_str(&contents).with_context(|| format!("Failed to parse {path:?}"))
+    }
+
+    /// Read the "Cargo.lock" file in the given directory as of the current Git `HEAD`,
+    /// i.e. before we started changing things.
+    ///
+    /// Returns an empty lockfile if it isn't tracked.
+    pub fn read_at_head(directory: &Path) -> anyhow::Result<Self> {
+        let path = directory.join("Cargo.lock");
+        let Some(contents) = git::show_at_head(&path)? else {
+            return Ok(Self::default());
+        };
+        toml::from_str(&contents).with_context(|| format!("Failed to parse {path:?} at HEAD"))
+    }
+
+    pub fn registry_packages(&self) -> impl Iterator<Item = &LockedPackage> {
+        self.packages
+            .iter()
+            .filter(|package| package.is_from_registry())
+    }
+
+    /// Packages that we can look up in the index.
+    pub fn crates_io_packages(&self) -> impl Iterator<Item = &LockedPackage> {
+        self.packages
+            .iter()
+            .filter(|package| package.is_from_crates_io())
+    }
+
+    /// Crates.io packages in this lockfile that weren't present (at the same version) in `before`.
+    pub fn new_crates_io_packages_since<'a>(
+        &'a self,
+        before: &'a Lockfile,
+    ) -> impl Iterator<Item = &'a LockedPackage> {
+        self.crates_io_packages()
+            .filter(move |package| !before.packages.contains(package))
+    }
+
+    /// Names of the crates that workspace members (and other local packages) depend on directly.
+    pub fn direct_dependency_names(&self) -> HashSet<&str> {
+        self.packages
+            .iter()
+            .filter(|package| package.source.is_none())
+            .flat_map(|package| &package.dependencies)
+            .filter_map(|dependency| dependency.split_whitespace().next())
+            .collect()
+    }
+
+    /// Work out what changed between this lockfile and a newer one.
+    pub fn diff(&self, after: &Lockfile) -> LockfileDiff {
+        let versions = |lockfile: &Lockfile| {
+            let mut versions: BTreeMap<String, BTreeSet<Version>> = BTreeMap::new();
+            for package in lockfile
+                .packages
+                .iter()
+                .filter(|package| package.source.is_some())
+            {
+                versions
+                    .entry(package.name.clone())
+                    .or_default()
+                    .insert(package.version.clone());
+            }
+            versions
+        };
+        let before_versions = versions(self);
+        let after_versions = versions(after);
+        let mut direct_names = self.direct_dependency_names();
+        direct_names.extend(after.direct_dependency_names());
+
+        let mut diff = LockfileDiff::default();
+        let names: BTreeSet<_> = before_versions
+            .keys()
+            .chain(after_versions.keys())
+            .collect();
+        for name in names {
+            let empty = BTreeSet::new();
+            let before = before_versions.get(name).unwrap_or(&empty);
+            let after = after_versions.get(name).unwrap_or(&empty);
+            let mut removed: Vec<_> = before.difference(after).collect();
+            let mut added: Vec<_> = after.difference(before).collect();
+
+            // Pair up old and new versions that are semver-compatible, oldest with oldest;
+            // whatever's left over was added or removed outright.
+            let mut changes = Vec::new();
+            removed.retain(|from| {
+                let Some(index) = added
+                    .iter()
+                    .position(|to| cargo::is_semver_compatible(from, to))
+                else {
+                    return true;
+                };
+                changes.push(PackageChange::Changed {
+                    name: name.clone(),
+                    from: (*from).clone(),
+                    to: added.remove(index).clone(),
+                });
+                false
+            });
+            for version in removed {
+                changes.push(PackageChange::Removed {
+                    name: name.clone(),
+                    version: version.clone(),
+                });
+            }
+            for version in added {
+                changes.push(PackageChange::Added {
+                    name: name.clone(),
+                    version: version.clone(),
+                });
+            }
+
+            if direct_names.contains(name.as_str()) {
+                diff.direct.extend(changes);
+            } else {
+                diff.transitive.extend(changes);
+            }
+        }
+        diff
+    }
+}
+
+/// Everything that changed in a lockfile, sorted by crate name.
+#[derive(serde::Serialize, serde::Deserialize, Default, Clone)]
+pub struct LockfileDiff {
+    /// Changes to crates that a workspace member depends on directly.
+    pub direct: Vec<PackageChange>,
+    pub transitive: Vec<PackageChange>,
+}
+
+impl LockfileDiff {
+    pub fn is_empty(&self) -> bool {
+        self.direct.is_empty() && self.transitive.is_empty()
+    }
+
+    pub fn commit_message_section(&self) -> String {
+        let mut section = String::new();
+        for (heading, changes) in [
+            ("Direct dependencies", &self.direct),
+            ("Transitive dependencies", &self.transitive),
+        ] {
+            if changes.is_empty() {
+                continue;
+            }
+            section += &format!("\n{heading}:\n\n");
+            for change in changes {
+                section += &format!("- {change}\n");
+            }
+        }
+        section
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub enum PackageChange {
+    Added {
+        name: String,
+        version: Version,
+    },
+    Removed {
+        name: String,
+        version: Version,
+    },
+    Changed {
+        name: String,
+        from: Version,
+        to: Version,
+    },
+}
+
+impl fmt::Display for PackageChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Added { name, version } => write!(f, "{name} {version} (added)"),
+            Self::Removed { name, version } => write!(f, "{name} {version} (removed)"),
+            Self::Changed { name, from, to } => write!(f, "{name} {from} → {to}"),
+        }
+    }
+}
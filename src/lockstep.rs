@@ -0,0 +1,153 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::Path,
+};
+
+use semver::Version;
+
+use crate::{
+    cargo,
+    cooldown::Cooldown,
+    index::Index,
+    lockfile::{LockedPackage, Lockfile},
+};
+
+/// Picks one version of each crate for every lockfile in the repo to use,
+/// so that they all agree even if they're updated minutes apart.
+///
+/// Targets come from the index, which is only fetched once per crate per run,
+/// so every lockfile sees the same ones.
+pub struct Lockstep<'a> {
+    index: &'a Index,
+    cooldown: Option<&'a Cooldown>,
+}
+
+/// A package that couldn't be moved to the version the rest of the repo is using,
+/// e.g. because something requires an exact version of it.
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct Diverged {
+    pub crate_name: String,
+    pub version: Version,
+    pub target: Version,
+    /// What Cargo said when we tried to move it, e.g. which requirement is in the way.
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+impl fmt::Display for Diverged {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} (couldn't move to {}",
+            self.crate_name, self.version, self.target
+        )?;
+        if let Some(reason) = &self.reason {
+            write!(f, ": {reason}")?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl<'a> Lockstep<'a> {
+    pub fn new(index: &'a Index, cooldown: Option<&'a Cooldown>) -> Self {
+        Self { index, cooldown }
+    }
+
+    /// The version every lockfile should use in place of `package`:
+    /// the newest semver-compatible release (that the cooldown allows).
+    ///
+    /// Pre-releases are left alone, and so are packages that are already newer than
+    /// the cooldown allows, since they were like that before we started.
+    pub fn target(&self, package: &LockedPackage) -> anyhow::Result<Option<Version>> {
+        if !package.version.pre.is_empty() {
+            return Ok(None);
+        }
+        let is_compatible =
+            |version: &Version| cargo::is_semver_compatible(version, &package.version);
+        let target = match self.cooldown {
+            Some(cooldown) => {
+                cooldown.newest_allowed_version(self.index, &package.name, is_compatible)?
+            }
+            None => self
+                .index
+                .newest_release(&package.name, |entry| is_compatible(&entry.vers))?,
+        };
+        Ok(target.filter(|target| *target >= package.version))
+    }
+
+    /// Move every crates.io package in the lockfile in `directory` to its target version,
+    /// returning the ones that couldn't be moved.
+    pub fn align(&self, directory: &Path, log: &mut Vec<String>) -> anyhow::Result<Vec<Diverged>> {
+        // Moving one package can move others, so look again after each move.
+        // Never try the same version of a package twice, so that this always finishes.
+        let mut attempted = HashSet::new();
+        let mut reasons = HashMap::new();
+        loop {
+            let lockfile = Lockfile::read(directory)?;
+            let mut next = None;
+            for package in lockfile.crates_io_packages() {
+                if attempted.contains(&package.spec()) {
+                    continue;
+                }
+                if let Some(target) = self.target(package)? {
+                    if target != package.version {
+                        next = Some((package.clone(), target));
+                        break;
+                    }
+                }
+            }
+            let Some((package, target)) = next else {
+                break;
+            };
+            attempted.insert(package.spec());
+            // If this fails, the package is held by a requirement we can't satisfy;
+            // that gets reported below.
+            match cargo::update_precise(directory, &package.spec(), &target) {
+                Ok(()) => log.push(format!(
+                    "    Moved {} to {target} to match the rest of the repo",
+                    package.spec()
+                )),
+                Err(err) => {
+                    reasons.insert(package.spec(), cargo_error(&err));
+                }
+            }
+        }
+
+        let mut diverged = Vec::new();
+        for package in Lockfile::read(directory)?.crates_io_packages() {
+            if let Some(target) = self.target(package)? {
+                if target != package.version {
+                    diverged.push(Diverged {
+                        crate_name: package.name.clone(),
+                        version: package.version.clone(),
+                        target,
+                        reason: reasons.get(&package.spec()).cloned().flatten(),
+                    });
+                }
+            }
+        }
+        Ok(diverged)
+    }
+}
+
+/// The line where Cargo says what went wrong, without the progress messages around it.
+fn cargo_error(err: &anyhow::Error) -> Option<String> {
+    err.root_cause()
+        .to_string()
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("error: "))
+        .map(str::to_string)
+}
+
+pub fn commit_message_section(diverged: &[Diverged]) -> String {
+    if diverged.is_empty() {
+        return String::new();
+    }
+    let mut section =
+        "\nThese couldn't be moved to the version used across the repo:\n\n".to_string();
+    for diverged in diverged {
+        section += &format!("- {diverged}\n");
+    }
+    section
+}
@@ -1,16 +1,23 @@
-mod cargo;
-mod command_ext;
-mod git;
-mod update_all;
-mod upgrade;
+use std::sync::Arc;
 
+use cargo_lockstep::{
+    audit::{self, AuditArgs},
+    dedupe::{self, DedupeArgs},
+    outdated::{self, OutdatedArgs},
+    runner::{self, SystemRunner},
+    update_all::{self, UpdateAllArgs},
+    upgrade::{self, UpgradeArgs},
+    why::{self, WhyArgs},
+};
 use clap::Parser;
-use update_all::UpdateAllArgs;
-use upgrade::UpgradeArgs;
 
 #[derive(clap::Parser)]
 #[command(version, about, long_about = None)]
 struct Cli {
+    /// Print every Git and Cargo command before running it.
+    #[arg(long, short, global = true)]
+    verbose: bool,
+
     #[command(subcommand)]
     subcommand: Subcommand,
 }
@@ -19,13 +26,26 @@ struct Cli {
 enum Subcommand {
     UpdateAll(UpdateAllArgs),
     Upgrade(UpgradeArgs),
+    Outdated(OutdatedArgs),
+    Dedupe(DedupeArgs),
+    Why(WhyArgs),
+    Audit(AuditArgs),
 }
 
 fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
+    runner::set_runner(Arc::new(SystemRunner {
+        verbose: cli.verbose,
+    }));
 
     match &cli.subcommand {
-        Subcommand::UpdateAll(update_all_args) => update_all::update_all(update_all_args),
-        Subcommand::Upgrade(upgrade_one_args) => upgrade::upgrade_one(upgrade_one_args),
+        Subcommand::UpdateAll(update_all_args) => {
+            update_all::update_all(update_all_args).map(|_| ())
+        }
+        Subcommand::Upgrade(upgrade_one_args) => upgrade::upgrade_one(upgrade_one_args).map(|_| ()),
+        Subcommand::Outdated(outdated_args) => outdated::outdated(outdated_args),
+        Subcommand::Dedupe(dedupe_args) => dedupe::dedupe(dedupe_args).map(|_| ()),
+        Subcommand::Why(why_args) => why::why(why_args).map(|_| ()),
+        Subcommand::Audit(audit_args) => audit::audit(audit_args).map(|_| ()),
     }
 }
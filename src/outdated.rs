@@ -0,0 +1,246 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fmt,
+    path::PathBuf,
+};
+
+use anyhow::Context;
+use semver::{Comparator, Op, Version, VersionReq};
+
+use crate::{
+    cargo,
+    discovery::Repo,
+    exclude::{ExcludeArgs, ExcludePaths},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct OutdatedArgs {
+    #[command(flatten)]
+    pub exclude_args: ExcludeArgs,
+}
+
+/// A project that directly depends on a crate.
+struct Usage {
+    project_name: String,
+    project_dir: PathBuf,
+    req: VersionReq,
+}
+
+/// The range of releases that are semver-compatible with a requirement,
+/// identified by its leftmost significant version component(s).
+/// E.g. "1" for "^1.2.3", or "0.10" for "0.10.1".
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone)]
+struct Series {
+    major: u64,
+    minor: Option<u64>,
+    patch: Option<u64>,
+}
+
+impl Series {
+    /// Only meaningful for simple requirements; others return `None`.
+    fn of(req: &VersionReq) -> Option<Self> {
+        let [comparator] = &req.comparators[..] else {
+            return None;
+        };
+        let Comparator {
+            op,
+            major,
+            minor,
+            patch,
+            ..
+        } = comparator;
+        if !matches!(op, Op::Caret | Op::Tilde | Op::Exact) {
+            return None;
+        }
+        Some(match (major, minor, patch) {
+            (0, Some(0), Some(patch)) => Self {
+                major: 0,
+                minor: Some(0),
+                patch: Some(*patch),
+            },
+            (0, Some(minor), _) => Self {
+                major: 0,
+                minor: Some(*minor),
+                patch: None,
+            },
+            (major, _, _) => Self {
+                major: *major,
+                minor: None,
+                patch: None,
+            },
+        })
+    }
+
+    fn contains(&self, version: &Version) -> bool {
+        self.major == version.major
+            && self.minor.is_none_or(|minor| minor == version.minor)
+            && self.patch.is_none_or(|patch| patch == version.patch)
+    }
+
+    fn is_older_than(&self, version: &Version) -> bool {
+        let floor = Version::new(self.major, self.minor.unwrap_or(0), self.patch.unwrap_or(0));
+        !self.contains(version) && floor < *version
+    }
+}
+
+impl fmt::Display for Series {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.major)?;
+        if let Some(minor) = self.minor {
+            write!(f, ".{minor}")?;
+        }
+        if let Some(patch) = self.patch {
+            write!(f, ".{patch}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A crate with a newer release that isn't semver-compatible with
+/// what at least one project in the repo requires.
+pub struct OutdatedCrate {
+    pub name: String,
+    pub latest_version: Version,
+    /// Projects using this crate, grouped by which series of releases they require.
+    ///
+    /// Requirements that aren't simple enough to have a series
+    /// are grouped by the requirement itself.
+    pub projects_by_requirement: BTreeMap<String, BTreeSet<String>>,
+    pub project_count: usize,
+}
+
+impl OutdatedCrate {
+    /// Whether different projects require different, incompatible versions.
+    pub fn has_mixed_requirements(&self) -> bool {
+        self.projects_by_requirement.len() > 1
+    }
+}
+
+pub fn outdated(outdated_args: &OutdatedArgs) -> anyhow::Result<()> {
+    let exclude_paths = ExcludePaths::from_args(&outdated_args.exclude_args)?;
+
+    println!("Looking for Cargo projects...");
+    let repo = Repo::discover(&exclude_paths)?;
+    let outdated_crates = find_outdated(&repo)?;
+    if outdated_crates.is_empty() {
+        println!("All dependencies allow their latest releases!");
+        return Ok(());
+    }
+
+    println!("These crates have newer semver-incompatible releases:");
+    for outdated_crate in &outdated_crates {
+        let mixed = if outdated_crate.has_mixed_requirements() {
+            " [mixed versions]"
+        } else {
+            ""
+        };
+        println!(
+            "  {} {} (used by {} projects){mixed}",
+            outdated_crate.name, outdated_crate.latest_version, outdated_crate.project_count
+        );
+        for (requirement, projects) in &outdated_crate.projects_by_requirement {
+            println!(
+                "    {requirement}: {}",
+                projects.iter().cloned().collect::<Vec<_>>().join(", ")
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Find every direct dependency in the repo with a newer semver-incompatible release.
+pub fn find_outdated(repo: &Repo) -> anyhow::Result<Vec<OutdatedCrate>> {
+    println!("Looking for dependencies in \"Cargo.toml\" files...");
+    let usages = find_direct_dependencies(repo)?;
+    if usages.is_empty() {
+        println!("Didn't find any dependencies from crates.io.");
+        return Ok(Vec::new());
+    }
+
+    println!(
+        "Looking up the latest versions of {} crates...",
+        usages.len()
+    );
+    let crate_names: Vec<_> = usages.keys().cloned().collect();
+    let latest_versions = cargo::get_latest_versions(&crate_names)
+        .context("Failed to get latest versions of dependencies")?;
+
+    let mut outdated_crates = Vec::new();
+    for (crate_name, usages) in usages {
+        let Some(latest_version) = latest_versions.get(&crate_name) else {
+            eprintln!(
+                "Warning: didn't find {crate_name:?} in latest versions; this shouldn't happen."
+            );
+            continue;
+        };
+
+        let mut projects_by_requirement: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        let mut is_outdated = false;
+        for usage in &usages {
+            let requirement = match Series::of(&usage.req) {
+                Some(series) => {
+                    is_outdated |= series.is_older_than(latest_version);
+                    series.to_string()
+                }
+                None => {
+                    is_outdated |= !usage.req.matches(latest_version);
+                    usage.req.to_string()
+                }
+            };
+            projects_by_requirement
+                .entry(requirement)
+                .or_default()
+                .insert(format!("{} ({:?})", usage.project_name, usage.project_dir));
+        }
+        if !is_outdated {
+            continue;
+        }
+
+        let project_count = usages
+            .iter()
+            .map(|usage| &usage.project_dir)
+            .collect::<HashSet<_>>()
+            .len();
+        outdated_crates.push(OutdatedCrate {
+            name: crate_name,
+            latest_version: latest_version.clone(),
+            projects_by_requirement,
+            project_count,
+        });
+    }
+
+    Ok(outdated_crates)
+}
+
+/// Find every crates.io dependency of every package in the repo, by crate name.
+///
+/// Crates from other registries can't be looked up, or might share a name with
+/// an unrelated crate on crates.io.
+fn find_direct_dependencies(repo: &Repo) -> anyhow::Result<BTreeMap<String, Vec<Usage>>> {
+    let mut usages: BTreeMap<String, Vec<Usage>> = BTreeMap::new();
+    for project in repo.projects() {
+        for dep in &project.dependencies {
+            if !dep.is_from_crates_io() {
+                continue;
+            }
+            let req = VersionReq::parse(&dep.req).with_context(|| {
+                format!("Failed to parse version requirement for {:?}", dep.name)
+            })?;
+            let crate_usages = usages.entry(dep.name.clone()).or_default();
+            // The same crate is often both a normal and dev dependency.
+            if crate_usages
+                .iter()
+                .any(|usage| usage.project_dir == project.dir && usage.req == req)
+            {
+                continue;
+            }
+            crate_usages.push(Usage {
+                project_name: project.name.clone(),
+                project_dir: project.dir.clone(),
+                req,
+            });
+        }
+    }
+    Ok(usages)
+}
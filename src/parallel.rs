@@ -0,0 +1,66 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc,
+    },
+};
+
+/// Run `work` on each item using up to `jobs` threads, and pass the results
+/// to `handle` in the original order of `items`, as soon as each is available.
+///
+/// This lets the slow part happen concurrently while anything that
+/// needs to be deterministic (output, commits) happens in order.
+///
+/// If `handle` returns an error, no new work is started, and the error
+/// is returned once all work that was already running has finished.
+pub fn run_in_order<T, R>(
+    items: &[T],
+    jobs: usize,
+    work: impl Fn(&T) -> R + Sync,
+    mut handle: impl FnMut(&T, R) -> anyhow::Result<()>,
+) -> anyhow::Result<()>
+where
+    T: Sync,
+    R: Send,
+{
+    let next_to_start = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel();
+
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, items.len().max(1)) {
+            let sender = sender.clone();
+            let (work, next_to_start, stop) = (&work, &next_to_start, &stop);
+            scope.spawn(move || loop {
+                if stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                let i = next_to_start.fetch_add(1, Ordering::SeqCst);
+                let Some(item) = items.get(i) else {
+                    break;
+                };
+                let result = work(item);
+                if sender.send((i, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        // Only the workers hold senders now, so the loop below ends when they're all done.
+        drop(sender);
+
+        let mut finished = BTreeMap::new();
+        let mut next_to_handle = 0;
+        for (i, result) in &receiver {
+            finished.insert(i, result);
+            while let Some(result) = finished.remove(&next_to_handle) {
+                if let Err(err) = handle(&items[next_to_handle], result) {
+                    stop.store(true, Ordering::SeqCst);
+                    return Err(err);
+                }
+                next_to_handle += 1;
+            }
+        }
+        Ok(())
+    })
+}
@@ -0,0 +1,183 @@
+use std::{fs, path::Path};
+
+use semver::Version;
+
+use crate::{cargo::Metadata, lockfile::PackageChange};
+
+/// Changelog file names to look for in a crate's source, in order of preference.
+const CHANGELOG_NAMES: &[&str] = &["CHANGELOG.md", "CHANGES.md", "RELEASES.md"];
+
+/// Don't let one chatty changelog take over the whole commit message.
+const MAX_EXCERPT_LINES: usize = 40;
+
+/// Where to read about what changed in a crate between two versions.
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct ReleaseNotes {
+    pub name: String,
+    pub from: Version,
+    pub to: Version,
+    /// Tag comparison and changelog links, if the crate says where its repository is.
+    pub links: Vec<String>,
+    /// The changelog entries for everything after `from` up to `to`,
+    /// if the new version's source is in the local registry cache and has a changelog.
+    pub excerpt: Option<String>,
+}
+
+/// Release notes for every crate that moved from one version to another,
+/// using `metadata` from after the move to find their repositories and sources.
+///
+/// Changelog excerpts can be long, so they're only included if `with_excerpts` is set.
+pub fn for_changes<'a>(
+    metadata: &Metadata,
+    changes: impl IntoIterator<Item = &'a PackageChange>,
+    with_excerpts: bool,
+) -> Vec<ReleaseNotes> {
+    changes
+        .into_iter()
+        .filter_map(|change| match change {
+            PackageChange::Changed { name, from, to } => {
+                Some(release_notes(metadata, name, from, to, with_excerpts))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn release_notes(
+    metadata: &Metadata,
+    name: &str,
+    from: &Version,
+    to: &Version,
+    with_excerpts: bool,
+) -> ReleaseNotes {
+    let mut packages = metadata
+        .packages
+        .iter()
+        .filter(|package| package.name == name && package.is_from_registry());
+    // The cooldown might have held `to` back since, but any version
+    // will do for finding the repository.
+    let package = packages
+        .clone()
+        .find(|package| package.version == *to)
+        .or_else(|| packages.next());
+
+    let changelog = package
+        .filter(|package| package.version == *to)
+        .and_then(|package| read_changelog(package.manifest_path.parent()?));
+    let changelog_name = changelog
+        .as_ref()
+        .map_or(CHANGELOG_NAMES[0], |(changelog_name, _)| changelog_name);
+    let links = package
+        .and_then(|package| package.repository.as_deref())
+        .map(|repository| links(repository, changelog_name, from, to))
+        .unwrap_or_default();
+    let excerpt = changelog
+        .filter(|_| with_excerpts)
+        .and_then(|(_, contents)| excerpt(&contents, from, to));
+
+    ReleaseNotes {
+        name: name.to_string(),
+        from: from.clone(),
+        to: to.clone(),
+        links,
+        excerpt,
+    }
+}
+
+/// Links to the changes between two tags and to the changelog.
+///
+/// There's no telling how a crate tags its releases, but `v1.2.3` is the most common.
+/// Hosts we don't know the URL layout of just get a link to the repository.
+fn links(repository: &str, changelog_name: &str, from: &Version, to: &Version) -> Vec<String> {
+    let repository = repository.trim().trim_end_matches('/');
+    let repository = repository.strip_suffix(".git").unwrap_or(repository);
+    let host = repository
+        .split_once("://")
+        .map_or(repository, |(_, rest)| rest)
+        .split('/')
+        .next()
+        .unwrap_or_default();
+    match host {
+        "github.com" => vec![
+            format!("{repository}/compare/v{from}...v{to}"),
+            format!("{repository}/blob/HEAD/{changelog_name}"),
+        ],
+        "gitlab.com" => vec![
+            format!("{repository}/-/compare/v{from}...v{to}"),
+            format!("{repository}/-/blob/HEAD/{changelog_name}"),
+        ],
+        _ => vec![repository.to_string()],
+    }
+}
+
+/// Find a changelog in an unpacked crate, returning its name and contents.
+fn read_changelog(package_dir: &Path) -> Option<(&'static str, String)> {
+    CHANGELOG_NAMES.iter().find_map(|changelog_name| {
+        let contents = fs::read_to_string(package_dir.join(changelog_name)).ok()?;
+        Some((*changelog_name, contents))
+    })
+}
+
+/// Pull the entries for versions after `from` up to and including `to` out of a changelog.
+///
+/// Any Markdown heading that mentions a version starts that version's entry,
+/// e.g. `## 1.2.3`, `## [v1.2.3] - 2024-01-01` or `# Version 1.2.3 (2024-01-01)`.
+/// Headings without a version (like `### Fixed`) belong to the entry they're in.
+fn excerpt(changelog: &str, from: &Version, to: &Version) -> Option<String> {
+    let mut lines = Vec::new();
+    let mut including = false;
+    for line in changelog.lines() {
+        if line.starts_with('#') {
+            if let Some(version) = heading_version(line) {
+                including = *from < version && version <= *to;
+            }
+        }
+        if including {
+            lines.push(line.trim_end());
+        }
+    }
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    if lines.is_empty() {
+        return None;
+    }
+    if lines.len() > MAX_EXCERPT_LINES {
+        lines.truncate(MAX_EXCERPT_LINES);
+        lines.push("…");
+    }
+    Some(lines.join("\n"))
+}
+
+fn heading_version(heading: &str) -> Option<Version> {
+    heading
+        .split(|c: char| c.is_whitespace() || "#[](),:".contains(c))
+        .find_map(|word| Version::parse(word.strip_prefix('v').unwrap_or(word)).ok())
+}
+
+pub fn commit_message_section(release_notes: &[ReleaseNotes]) -> String {
+    if release_notes.is_empty() {
+        return String::new();
+    }
+    let mut section = "\nRelease notes:\n\n".to_string();
+    let mut after_excerpt = false;
+    for notes in release_notes {
+        // Otherwise Markdown would treat the next item as part of the quote.
+        if after_excerpt {
+            section += "\n";
+        }
+        section += &format!("- {} {} → {}\n", notes.name, notes.from, notes.to);
+        for link in &notes.links {
+            section += &format!("  - {link}\n");
+        }
+        after_excerpt = notes.excerpt.is_some();
+        if let Some(excerpt) = &notes.excerpt {
+            section += "\n";
+            for line in excerpt.lines() {
+                section += format!("  > {line}").trim_end();
+                section += "\n";
+            }
+        }
+    }
+    section
+}
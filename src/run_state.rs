@@ -0,0 +1,60 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+
+use crate::{branch::Branch, git, update_all::ProjectUpdate};
+
+const RUN_STATE_FILE_NAME: &str = "cargo-lockstep-run.json";
+
+/// Progress through an `update-all` run, saved after every project
+/// so that an interrupted run can be picked up again with `--resume`.
+///
+/// This lives in the Git directory so that it doesn't dirty the working tree.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct RunState {
+    pub branch: Branch,
+    /// Projects that are finished with, in the order they were handled.
+    pub projects: Vec<ProjectUpdate>,
+}
+
+impl RunState {
+    pub fn new(branch: Branch) -> Self {
+        Self {
+            branch,
+            projects: Vec::new(),
+        }
+    }
+
+    /// Load the state of an interrupted run, if there is one.
+    pub fn load() -> anyhow::Result<Option<Self>> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = std::fs::read(&path).with_context(|| format!("Failed to read {path:?}"))?;
+        let state = serde_json::from_slice(&contents)
+            .with_context(|| format!("Failed to parse run state in {path:?}"))?;
+        Ok(Some(state))
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let path = Self::path()?;
+        let contents = serde_json::to_vec_pretty(self).context("Failed to serialize run state")?;
+        std::fs::write(&path, contents).with_context(|| format!("Failed to write {path:?}"))
+    }
+
+    /// Forget about the run, because it finished.
+    pub fn clear() -> anyhow::Result<()> {
+        let path = Self::path()?;
+        if path.exists() {
+            std::fs::remove_file(&path).with_context(|| format!("Failed to remove {path:?}"))?;
+        }
+        Ok(())
+    }
+
+    fn path() -> anyhow::Result<PathBuf> {
+        Ok(git::git_dir()
+            .context("Failed to find Git directory")?
+            .join(RUN_STATE_FILE_NAME))
+    }
+}
@@ -0,0 +1,234 @@
+use std::{
+    fmt,
+    io::Read,
+    path::PathBuf,
+    process::{Child, Command, ExitStatus, Output, Stdio},
+    sync::{Arc, Mutex, RwLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Runs the external commands (Git, Cargo, curl) that everything else is built on.
+///
+/// The runner in use is global, so that the `git` and `cargo` modules don't need
+/// one passed through every call. Swap it out with [`set_runner`], e.g. to trace
+/// every command or to fake them in tests.
+pub trait Runner: Send + Sync {
+    /// Run the command to completion, capturing its stdout and stderr.
+    ///
+    /// A nonzero exit status isn't an error here; that's up to the caller.
+    fn run(&self, cmd: &mut Command) -> std::io::Result<Output>;
+
+    /// Like [`Runner::run`], but kill the command if it's still going after `timeout`,
+    /// and fail with [`std::io::ErrorKind::TimedOut`].
+    ///
+    /// Runners that can't enforce a timeout may just run the command normally.
+    fn run_with_timeout(&self, cmd: &mut Command, _timeout: Duration) -> std::io::Result<Output> {
+        self.run(cmd)
+    }
+}
+
+/// Actually runs commands.
+#[derive(Default)]
+pub struct SystemRunner {
+    /// Print every command to stderr before running it.
+    pub verbose: bool,
+}
+
+impl Runner for SystemRunner {
+    fn run(&self, cmd: &mut Command) -> std::io::Result<Output> {
+        if self.verbose {
+            eprintln!("+ {}", Invocation::of(cmd));
+        }
+        let output = cmd.stdin(Stdio::null()).output()?;
+        if self.verbose && !output.status.success() {
+            eprintln!("  ({})", output.status);
+        }
+        Ok(output)
+    }
+
+    fn run_with_timeout(&self, cmd: &mut Command, timeout: Duration) -> std::io::Result<Output> {
+        if self.verbose {
+            eprintln!("+ {} (timeout {}s)", Invocation::of(cmd), timeout.as_secs());
+        }
+        // Give the command its own process group, so that on timeout we can kill
+        // everything it started too (e.g. `cargo test` under `sh -c`), not just the shell.
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(cmd, 0);
+        let mut child = cmd
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        // Read output as it comes so that the command doesn't block on a full pipe.
+        let stdout = read_to_end_in_background(child.stdout.take());
+        let stderr = read_to_end_in_background(child.stderr.take());
+        let deadline = Instant::now() + timeout;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                kill_process_group(&mut child)?;
+                child.wait()?;
+                if self.verbose {
+                    eprintln!("  (timed out)");
+                }
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("`{}` timed out", Invocation::of(cmd)),
+                ));
+            }
+            thread::sleep(Duration::from_millis(50));
+        };
+        if self.verbose && !status.success() {
+            eprintln!("  ({status})");
+        }
+        Ok(Output {
+            status,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        })
+    }
+}
+
+/// Kill a child that was started in its own process group, along with everything it started.
+#[cfg(unix)]
+fn kill_process_group(child: &mut Child) -> std::io::Result<()> {
+    // The group's ID is the child's PID; a negative PID tells `kill` to signal the whole group.
+    let status = Command::new("kill")
+        .args(["-KILL", "--", &format!("-{}", child.id())])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()?;
+    if !status.success() {
+        child.kill()?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn kill_process_group(child: &mut Child) -> std::io::Result<()> {
+    child.kill()
+}
+
+fn read_to_end_in_background(
+    pipe: Option<impl Read + Send + 'static>,
+) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            // Whatever we managed to read is better than nothing.
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+static RUNNER: RwLock<Option<Arc<dyn Runner>>> = RwLock::new(None);
+
+/// Use `runner` for all commands from now on.
+pub fn set_runner(runner: Arc<dyn Runner>) {
+    *RUNNER.write().unwrap() = Some(runner);
+}
+
+/// The runner to use for commands; a quiet [`SystemRunner`] unless something else was set.
+pub fn runner() -> Arc<dyn Runner> {
+    RUNNER
+        .read()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| Arc::new(SystemRunner::default()))
+}
+
+/// A command that was (or would have been) run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invocation {
+    pub program: String,
+    pub args: Vec<String>,
+    /// Directory it was run in, if not the current one.
+    pub dir: Option<PathBuf>,
+}
+
+impl Invocation {
+    pub fn of(cmd: &Command) -> Self {
+        Self {
+            program: cmd.get_program().to_string_lossy().into_owned(),
+            args: cmd
+                .get_args()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+            dir: cmd.get_current_dir().map(|dir| dir.to_owned()),
+        }
+    }
+}
+
+impl fmt::Display for Invocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.program)?;
+        for arg in &self.args {
+            if arg.is_empty() || arg.contains(char::is_whitespace) {
+                write!(f, " {arg:?}")?;
+            } else {
+                write!(f, " {arg}")?;
+            }
+        }
+        if let Some(dir) = &self.dir {
+            write!(f, " (in {dir:?})")?;
+        }
+        Ok(())
+    }
+}
+
+type Respond = dyn Fn(&Invocation) -> Output + Send + Sync;
+
+/// Doesn't run anything; records every command and answers it with `respond`.
+pub struct FakeRunner {
+    respond: Box<Respond>,
+    invocations: Mutex<Vec<Invocation>>,
+}
+
+impl FakeRunner {
+    pub fn new(respond: impl Fn(&Invocation) -> Output + Send + Sync + 'static) -> Self {
+        Self {
+            respond: Box::new(respond),
+            invocations: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Everything that has been run so far, in order.
+    pub fn invocations(&self) -> Vec<Invocation> {
+        self.invocations.lock().unwrap().clone()
+    }
+}
+
+impl Runner for FakeRunner {
+    fn run(&self, cmd: &mut Command) -> std::io::Result<Output> {
+        let invocation = Invocation::of(cmd);
+        let output = (self.respond)(&invocation);
+        self.invocations.lock().unwrap().push(invocation);
+        Ok(output)
+    }
+}
+
+/// Make up the output of a command, for use with [`FakeRunner`].
+pub fn fake_output(exit_code: i32, stdout: &str, stderr: &str) -> Output {
+    Output {
+        status: exit_status(exit_code),
+        stdout: stdout.as_bytes().to_vec(),
+        stderr: stderr.as_bytes().to_vec(),
+    }
+}
+
+#[cfg(unix)]
+fn exit_status(exit_code: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt as _;
+    ExitStatus::from_raw(exit_code << 8)
+}
+
+#[cfg(windows)]
+fn exit_status(exit_code: i32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt as _;
+    ExitStatus::from_raw(exit_code as u32)
+}
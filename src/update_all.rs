@@ -1,115 +1,595 @@
-use std::{path::PathBuf, process::Command, str::FromStr};
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
 
 use anyhow::Context;
-use walkdir::WalkDir;
 
-use crate::{command_ext::CommandExt as _, git};
+use crate::{
+    audit::{self, AdvisoryDb, Vulnerability},
+    branch::{Branch, BranchArgs, BranchStatus},
+    cargo,
+    check::{self, Check, CheckResult},
+    config::Config,
+    cooldown::{self, Cooldown, HeldBack},
+    discovery::Repo,
+    exclude::{ExcludeArgs, ExcludePaths},
+    git,
+    index::Index,
+    lockfile::{Lockfile, LockfileDiff},
+    lockstep::{self, Diverged, Lockstep},
+    parallel,
+    release_notes::{self, ReleaseNotes},
+    run_state::RunState,
+    yanked::{self, YankedOutcome, YankedPin},
+};
 
 #[derive(clap::Args, Debug)]
 pub struct UpdateAllArgs {
-    /// Exclude "Cargo.lock" files or containing directories.
+    #[command(flatten)]
+    pub exclude_args: ExcludeArgs,
+
+    /// Run checks after applying updates, and don't commit if they fail.
     ///
-    /// Must be specified relative to the current working directory.
+    /// This is `cargo check --all-targets` unless other checks are
+    /// configured in "cargo-lockstep.toml".
     #[arg(long)]
-    exclude: Vec<String>,
+    pub check: bool,
+
+    /// Number of projects to update (and check) at the same time.
+    ///
+    /// Commits are still made one project at a time, in the same order
+    /// as when running sequentially.
+    #[arg(long, short = 'j', value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    pub jobs: u16,
 
-    /// Run `cargo check` after applying updates.
+    /// Don't adopt versions that were published less than this many days ago.
+    ///
+    /// Packages that would have been updated to a newer version are instead
+    /// held back to the newest compatible version that is old enough.
+    #[arg(long, value_name = "DAYS")]
+    pub min_age: Option<u32>,
+
+    /// Make every lockfile agree on the version of each crate.
+    ///
+    /// After `cargo update`, every package is moved to the newest compatible
+    /// release with `cargo update --precise`, using the same target version
+    /// for every lockfile in the repo. Packages that can't be moved are listed at the end.
     #[arg(long)]
-    check: bool,
+    pub lockstep: bool,
+
+    /// Only update crates with RustSec advisories against them, e.g. for an urgent fix branch.
+    ///
+    /// Each vulnerable package is updated with `cargo update --package`,
+    /// and everything else is left alone. Advisories that still apply are listed at the end.
+    #[arg(long, conflicts_with_all = ["lockstep", "min_age"])]
+    pub security_only: bool,
+
+    /// Local checkout of <https://github.com/rustsec/advisory-db> for `--security-only`.
+    ///
+    /// Defaults to "advisory-db" in Cargo's home directory, which is where `cargo audit` keeps it.
+    #[arg(long, value_name = "PATH", requires = "security_only")]
+    pub advisory_db: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub branch_args: BranchArgs,
+
+    /// Continue an interrupted run rather than starting a new one.
+    ///
+    /// Switches back to the branch the run was using, skips projects
+    /// that were already finished, and picks up from there.
+    #[arg(long)]
+    pub resume: bool,
 }
 
-pub fn update_all(update_all_args: &UpdateAllArgs) -> anyhow::Result<()> {
-    // Validate that all exclude rules point to actual paths.
-    // (It's bad to let people think that their arguments are doing something if they're not!)
-    let mut exclude_paths = Vec::new();
-    for exclude in &update_all_args.exclude {
-        let exclude_path =
-            PathBuf::from_str(exclude).with_context(|| "\"{exclude:?}\" isn't a valid path")?;
-        if !exclude_path.exists() {
-            anyhow::bail!("Excluded path {exclude_path:?} doesn't exist!");
-        }
-        exclude_paths.push(
-            exclude_path
-                .canonicalize()
-                .with_context(|| format!("Failed to canonicalize exclude path {exclude_path:?}"))?,
-        );
-    }
+/// What happened when updating a single project.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct ProjectUpdate {
+    /// Directory containing the project's lockfile.
+    pub dir: PathBuf,
+    /// Whether the lockfile changed, and so was committed.
+    pub changed: bool,
+    pub held_back: Vec<HeldBack>,
+    pub yanked_pins: Vec<YankedPin>,
+    /// Checks that passed after updating.
+    #[serde(default)]
+    pub checks: Vec<CheckResult>,
+    /// Packages that couldn't be moved to the version used across the repo.
+    #[serde(default)]
+    pub diverged: Vec<Diverged>,
+    /// What changed in the lockfile.
+    #[serde(default)]
+    pub changes: LockfileDiff,
+    /// Where to read about the crates that moved to new versions.
+    #[serde(default)]
+    pub release_notes: Vec<ReleaseNotes>,
+    /// Advisories that applied before updating but not after (only with `--security-only`).
+    #[serde(default)]
+    pub fixed_advisories: Vec<Vulnerability>,
+    /// Advisories that still apply after updating (only with `--security-only`).
+    #[serde(default)]
+    pub remaining_advisories: Vec<Vulnerability>,
+}
+
+/// Which packages to update in each lockfile.
+enum Scope<'a> {
+    /// Everything `cargo update` will, maybe then moving every lockfile to the same versions.
+    Everything { lockstep: Option<Lockstep<'a>> },
+    /// Just packages with advisories against them.
+    SecurityOnly(AdvisoryDb),
+}
+
+/// Everything `update_all` did.
+pub struct UpdateAllReport {
+    /// The branch the updates were committed to.
+    pub branch_name: String,
+    pub branch_status: BranchStatus,
+    /// Every project that was updated, in the order they were committed.
+    pub projects: Vec<ProjectUpdate>,
+}
+
+/// Update every lockfile in the repo, committing each one separately
+/// on a new branch.
+pub fn update_all(update_all_args: &UpdateAllArgs) -> anyhow::Result<UpdateAllReport> {
+    let exclude_paths = ExcludePaths::from_args(&update_all_args.exclude_args)?;
+    let config = Config::load().context("Failed to load config")?;
+    let advisory_db = update_all_args
+        .security_only
+        .then(|| AdvisoryDb::open(update_all_args.advisory_db.as_deref()))
+        .transpose()
+        .context("Failed to read advisory database")?;
 
     // TODO: Find git root by default instead of just operating from CWD.
     // (Have option for operating just within CWD.)
 
-    if !git::is_working_tree_clean().context("Failed to check if working tree is clean")? {
-        anyhow::bail!("Working tree is not clean; please commit or stash your changes first.");
-    }
+    let previous_run = RunState::load().context("Failed to load state of previous run")?;
+    let mut run_state = if update_all_args.resume {
+        let Some(run_state) = previous_run else {
+            anyhow::bail!("There's no interrupted run to resume.");
+        };
+        println!(
+            "Resuming run on branch {:?}, where {} projects were already done...",
+            run_state.branch.name,
+            run_state.projects.len()
+        );
+        if let Some(branch_name) = &update_all_args.branch_args.branch {
+            if *branch_name != run_state.branch.name {
+                anyhow::bail!(
+                    "The interrupted run was on branch {:?}, not {branch_name:?}",
+                    run_state.branch.name
+                );
+            }
+        }
+        if git::current_branch()? != run_state.branch.name {
+            if !git::is_working_tree_clean().context("Failed to check if working tree is clean")? {
+                anyhow::bail!(
+                    "Working tree is not clean; please commit or stash your changes first."
+                );
+            }
+            git::switch_to_branch(&run_state.branch.name)
+                .context("Failed to switch to branch of interrupted run")?;
+        }
+        run_state
+    } else {
+        if let Some(previous_run) = &previous_run {
+            println!(
+                "Starting a new run; use `--resume` to continue the interrupted one on branch {:?} instead.",
+                previous_run.branch.name
+            );
+        }
 
-    let base_branch = git::guess_base_branch().context("Failed to guess base branch")?;
-    git::fetch(&base_branch).context("Failed to update base branch from origin")?;
+        if !git::is_working_tree_clean().context("Failed to check if working tree is clean")? {
+            anyhow::bail!("Working tree is not clean; please commit or stash your changes first.");
+        }
+
+        let branch_prefix = if update_all_args.security_only {
+            "cargo-lockstep-security"
+        } else {
+            "cargo-lockstep-update-all"
+        };
+        let branch = Branch::start(update_all_args.branch_args.branch.as_deref(), branch_prefix)
+            .context("Failed to create branch for applying updates")?;
+        RunState::new(branch)
+    };
+    run_state.save().context("Failed to save run state")?;
 
-    // Make a branch based on the current time.
-    let compact_now = chrono::Utc::now().format("%Y%m%d%H%M%S");
-    let new_branch_name = format!("cargo-lockstep-update-all-{compact_now}");
-    git::switch_to_new_branch(&new_branch_name, &format!("origin/{base_branch}"))
-        .context("Failed to create branch for applying updates")?;
+    let cooldown = update_all_args.min_age.map(Cooldown::new);
+    let index = Index::from_env();
+    let scope = match advisory_db {
+        Some(advisory_db) => Scope::SecurityOnly(advisory_db),
+        None => Scope::Everything {
+            lockstep: update_all_args
+                .lockstep
+                .then(|| Lockstep::new(&index, cooldown.as_ref())),
+        },
+    };
 
     // Find all the Cargo lockfiles so we can run `cargo update` in those directories.
     println!("Looking for \"Cargo.lock\" files...");
-    let mut any_changes = false;
-    for entry in WalkDir::new(".").follow_links(false).into_iter() {
-        // TODO: More helpful error.
-        let entry = entry.context("Couldn't read dir entry")?;
-
-        let file_name = entry.file_name().to_string_lossy();
-        if file_name != "Cargo.lock" {
+    let repo = Repo::discover(&exclude_paths)?;
+    let mut lockfile_dirs = Vec::new();
+    for workspace in &repo.workspaces {
+        if workspace.has_lockfile && workspace.lockfile_excluded {
+            println!(
+                "  Skipping {:?} because it matches an excluded path.",
+                workspace.root
+            );
             continue;
         }
-
-        let dir = entry
-            .path()
-            .parent()
-            .context("Cargo lockfile didn't have a parent directory")?;
-
-        let absolute_path = entry
-            .path()
-            .canonicalize()
-            .with_context(|| format!("Failed to canonicalize path {:?}", entry.path()))?;
-        if exclude_paths
+        if !workspace.has_lockfile {
+            continue;
+        }
+        if run_state
+            .projects
             .iter()
-            .any(|exclude_path| absolute_path.starts_with(exclude_path))
+            .any(|project| project.dir == workspace.root)
         {
-            println!("  Skipping {dir:?} because it matches an excluded path.");
+            println!(
+                "  Skipping {:?} because it was already done before resuming.",
+                workspace.root
+            );
             continue;
         }
+        lockfile_dirs.push(workspace.root.clone());
+    }
 
-        println!("  Running `cargo update` in {dir:?}...");
-
-        let mut cmd = Command::new("cargo");
-        cmd.arg("update").current_dir(dir);
-        // TODO: Don't blow up the whole process if we fail in here.
-        cmd.success_or_err().context("`cargo update` failed")?;
-        if git::is_working_tree_clean()? {
-            println!("    Already up-to-date!");
-            continue;
+    if update_all_args.resume {
+        // Whatever was happening to these lockfiles when we were interrupted
+        // is about to happen again from scratch.
+        for dir in &lockfile_dirs {
+            git::discard_path_changes(&dir.join("Cargo.lock"))
+                .context("Failed to discard changes to lockfile")?;
         }
+        if !git::is_working_tree_clean().context("Failed to check if working tree is clean")? {
+            anyhow::bail!("Working tree is not clean; please commit or stash your changes first.");
+        }
+    }
 
-        any_changes = true;
+    // If all projects share a target directory, Cargo will make concurrent checks
+    // wait for each other anyway, so we may as well take turns politely
+    // rather than having Cargo complain about it.
+    let shared_target_dir = std::env::var_os("CARGO_TARGET_DIR").is_some();
+    if update_all_args.jobs > 1 && update_all_args.check && shared_target_dir {
+        println!("  `CARGO_TARGET_DIR` is set, so checks will run one at a time.");
+    }
+    let check_lock = Mutex::new(());
 
-        if update_all_args.check {
-            println!("  Running `cargo check --all-targets` in {dir:?}...");
-            let mut cmd = Command::new("cargo");
-            cmd.args(["check", "--all-targets"]).current_dir(dir);
-            cmd.success_or_err().context("`cargo check` failed")?;
-        }
+    let mut any_changes = run_state.projects.iter().any(|project| project.changed);
+    let mut handled = 0;
+    let result = parallel::run_in_order(
+        &lockfile_dirs,
+        update_all_args.jobs.into(),
+        |dir| {
+            let mut log = Vec::new();
+            let checks = update_all_args
+                .check
+                .then(|| check::checks_for(&config, dir));
+            let result = update_project(
+                dir,
+                checks.as_deref(),
+                cooldown.as_ref(),
+                &index,
+                &scope,
+                shared_target_dir.then_some(&check_lock),
+                &mut log,
+            );
+            (log, result)
+        },
+        |dir, (log, result)| {
+            handled += 1;
+            for line in &log {
+                println!("{line}");
+            }
+            // TODO: Don't blow up the whole process if we fail in here.
+            let update = result?;
+
+            if !update.changed {
+                run_state.projects.push(update);
+                return run_state.save().context("Failed to save run state");
+            }
+            any_changes = true;
 
-        println!("    Committing updates...");
-        let message = format!("cargo update in {dir:?}\n\nAll semver-compatible-updates, by running `cargo update`.\nThis commit was created by `cargo-lockstep`.");
-        git::commit(&message).context("Failed to commit changes")?;
+            println!("    Committing updates...");
+            let mut message = if update_all_args.security_only {
+                format!("Security updates in {dir:?}\n\nCrates with RustSec advisories against them, by running `cargo update --package` for each.\n")
+            } else {
+                format!("cargo update in {dir:?}\n\nAll semver-compatible-updates, by running `cargo update`.\n")
+            };
+            if !update.changes.is_empty() {
+                message += &update.changes.commit_message_section();
+                message += "\n";
+            }
+            if !update.fixed_advisories.is_empty() || !update.remaining_advisories.is_empty() {
+                message += &audit::commit_message_section(
+                    &update.fixed_advisories,
+                    &update.remaining_advisories,
+                );
+                message += "\n";
+            }
+            if !update.release_notes.is_empty() {
+                message += &release_notes::commit_message_section(&update.release_notes);
+                message += "\n";
+            }
+            if let Some(cooldown) = &cooldown {
+                if !update.held_back.is_empty() {
+                    message += &cooldown::commit_message_section(cooldown, &update.held_back);
+                    message += "\n";
+                }
+            }
+            if !update.yanked_pins.is_empty() {
+                message += &yanked::commit_message_section(&update.yanked_pins);
+                message += "\n";
+            }
+            if !update.diverged.is_empty() {
+                message += &lockstep::commit_message_section(&update.diverged);
+                message += "\n";
+            }
+            if !update.checks.is_empty() {
+                message += &check::commit_message_section(&update.checks);
+                message += "\n";
+            }
+            message += "This commit was created by `cargo-lockstep`.";
+            git::commit_paths(&message, &[&dir.join("Cargo.lock")])
+                .context("Failed to commit changes")?;
+
+            run_state.projects.push(update);
+            run_state.save().context("Failed to save run state")
+        },
+    );
+    if let Err(err) = result {
+        // Projects after the one that failed may have been updated concurrently;
+        // throw those changes away so we end up where a sequential run would have stopped.
+        for dir in lockfile_dirs.iter().skip(handled) {
+            git::discard_path_changes(&dir.join("Cargo.lock"))
+                .context("Failed to discard changes to lockfile")?;
+        }
+        eprintln!(
+            "Stopped partway through; once the problem is fixed, run again with `--resume` to continue."
+        );
+        return Err(err);
     }
+    RunState::clear().context("Failed to clean up run state")?;
+    let RunState { branch, projects } = run_state;
 
     if !any_changes {
         println!("All \"Cargo.lock\" files were already up-to-date!");
     }
 
-    println!("Updates applied! You can now push this branch and make a pull-request.");
+    if let Some(cooldown) = &cooldown {
+        if projects.iter().any(|project| !project.held_back.is_empty()) {
+            println!(
+                "These were held back because their newest releases are less than {} days old:",
+                cooldown.min_age_days()
+            );
+            for project in &projects {
+                for held_back in &project.held_back {
+                    println!("  {held_back} in {:?}", project.dir);
+                }
+            }
+        }
+    }
+
+    if projects
+        .iter()
+        .any(|project| !project.yanked_pins.is_empty())
+    {
+        println!("These lockfiles had yanked versions pinned:");
+        for project in &projects {
+            for pin in &project.yanked_pins {
+                println!("  {pin} in {:?}", project.dir);
+            }
+        }
+    }
+
+    if projects.iter().any(|project| !project.diverged.is_empty()) {
+        println!("These couldn't be moved to the version used across the repo:");
+        for project in &projects {
+            for diverged in &project.diverged {
+                println!("  {diverged} in {:?}", project.dir);
+            }
+        }
+    }
+
+    if projects
+        .iter()
+        .any(|project| !project.remaining_advisories.is_empty())
+    {
+        println!("These advisories still apply:");
+        for project in &projects {
+            for vulnerability in &project.remaining_advisories {
+                println!("  {vulnerability} in {:?}", project.dir);
+            }
+        }
+    }
+
+    if projects.iter().any(|project| !project.checks.is_empty()) {
+        println!("These checks passed:");
+        for project in &projects {
+            for result in &project.checks {
+                println!("  {result}");
+            }
+        }
+    }
+
+    println!("Updates applied!");
+    let branch_status = branch.finish(
+        update_all_args.branch_args.push,
+        update_all_args.branch_args.force_with_lease,
+        any_changes,
+    )?;
+
+    Ok(UpdateAllReport {
+        branch_name: branch.name,
+        branch_status,
+        projects,
+    })
+}
+
+/// Run `cargo update` (and maybe checks) in a single project,
+/// without committing anything.
+///
+/// This may run concurrently with other projects, so it mustn't touch anything
+/// outside of the project's own lockfile. Output is buffered in `log` rather than
+/// printed, so that output from projects being updated at the same time doesn't get mixed up.
+fn update_project(
+    dir: &Path,
+    checks: Option<&[Check]>,
+    cooldown: Option<&Cooldown>,
+    index: &Index,
+    scope: &Scope,
+    check_lock: Option<&Mutex<()>>,
+    log: &mut Vec<String>,
+) -> anyhow::Result<ProjectUpdate> {
+    log.push(format!("  Running `cargo update` in {dir:?}..."));
+
+    // Git commands aren't safe to run concurrently (they fight over the index lock),
+    // so compare the lockfile's contents ourselves to see if anything changed.
+    let lockfile_path = dir.join("Cargo.lock");
+    let original_contents = std::fs::read(&lockfile_path)
+        .with_context(|| format!("Failed to read {lockfile_path:?}"))?;
+
+    let before = Lockfile::read(dir)?;
+    let mut yanked_before = Vec::new();
+    let mut vulnerable_before = Vec::new();
+    let report = match scope {
+        Scope::Everything { .. } => {
+            yanked_before = yanked::find_yanked(index, &before, log);
+            if !yanked_before.is_empty() {
+                log.push(format!("    Found yanked versions in {dir:?}:"));
+                for package in &yanked_before {
+                    log.push(format!("      {}", package.spec()));
+                }
+            }
+            cargo::update(dir)?
+        }
+        Scope::SecurityOnly(advisory_db) => {
+            vulnerable_before = advisory_db.vulnerabilities(&before);
+            let mut package_specs: Vec<_> =
+                vulnerable_before.iter().map(Vulnerability::spec).collect();
+            package_specs.sort();
+            package_specs.dedup();
+            if package_specs.is_empty() {
+                String::new()
+            } else {
+                log.push(format!("    Found vulnerable versions in {dir:?}:"));
+                for vulnerability in &vulnerable_before {
+                    log.push(format!("      {vulnerability}"));
+                }
+                cargo::update_packages(dir, &package_specs)?
+            }
+        }
+    };
+    log.extend(
+        report
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| format!("    {}", line.trim())),
+    );
+
+    let mut held_back = Vec::new();
+    if let Some(cooldown) = cooldown {
+        held_back = cooldown
+            .hold_back_lockfile(index, dir, &before, log)
+            .context("Failed to hold back recently published versions")?;
+        for held_back in &held_back {
+            log.push(format!("    Held back {held_back}"));
+        }
+    }
+
+    let mut diverged = Vec::new();
+    if let Scope::Everything { lockstep } = scope {
+        // `cargo update` will usually have moved yanked versions already,
+        // but the cooldown might have put them back.
+        yanked::move_off_yanked(index, dir, cooldown, log)
+            .context("Failed to move off yanked versions")?;
+        if let Some(lockstep) = lockstep {
+            diverged = lockstep
+                .align(dir, log)
+                .context("Failed to move packages to the versions used across the repo")?;
+            for diverged in &diverged {
+                log.push(format!("    Diverged {diverged}"));
+            }
+        }
+    }
+    let after = Lockfile::read(dir)?;
+    let yanked_pins = yanked::outcomes(yanked_before, &after);
+    for pin in &yanked_pins {
+        log.push(format!("    Yanked {pin}"));
+    }
+    let any_still_yanked = yanked_pins
+        .iter()
+        .any(|pin| matches!(pin.outcome, YankedOutcome::StillPinned));
+    let mut remaining_advisories = Vec::new();
+    if let Scope::SecurityOnly(advisory_db) = scope {
+        remaining_advisories = advisory_db.vulnerabilities(&after);
+    }
+    let fixed_advisories: Vec<_> = vulnerable_before
+        .into_iter()
+        // The version will have changed if an update didn't get far enough.
+        .filter(|vulnerability| {
+            !remaining_advisories.iter().any(|remaining| {
+                remaining.advisory_id == vulnerability.advisory_id
+                    && remaining.crate_name == vulnerability.crate_name
+            })
+        })
+        .collect();
+
+    let new_contents = std::fs::read(&lockfile_path)
+        .with_context(|| format!("Failed to read {lockfile_path:?}"))?;
+    if new_contents == original_contents {
+        if any_still_yanked {
+            log.push("    No updates, but some yanked versions are still pinned.".to_string());
+        } else {
+            log.push("    Already up-to-date!".to_string());
+        }
+        return Ok(ProjectUpdate {
+            dir: dir.to_owned(),
+            changed: false,
+            held_back,
+            yanked_pins,
+            checks: Vec::new(),
+            diverged,
+            changes: LockfileDiff::default(),
+            release_notes: Vec::new(),
+            fixed_advisories,
+            remaining_advisories,
+        });
+    }
+
+    let mut check_results = Vec::new();
+    if let Some(checks) = checks {
+        let _guard = check_lock.map(|check_lock| check_lock.lock().unwrap());
+        check_results = check::run_checks(dir, checks, log)?;
+    }
+
+    let changes = before.diff(&after);
+    // Release notes are nice to have, so don't give up on the update without them.
+    let release_notes = match cargo::metadata(dir, false) {
+        Ok(metadata) => {
+            let mut release_notes = release_notes::for_changes(&metadata, &changes.direct, true);
+            release_notes.extend(release_notes::for_changes(
+                &metadata,
+                &changes.transitive,
+                false,
+            ));
+            release_notes
+        }
+        Err(err) => {
+            log.push(format!("    Couldn't find release notes: {err:#}"));
+            Vec::new()
+        }
+    };
 
-    Ok(())
+    Ok(ProjectUpdate {
+        dir: dir.to_owned(),
+        changed: true,
+        held_back,
+        yanked_pins,
+        checks: check_results,
+        diverged,
+        changes,
+        release_notes,
+        fixed_advisories,
+        remaining_advisories,
+    })
 }
@@ -1,50 +1,180 @@
-use std::{path::PathBuf, process::Command, str::FromStr as _};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+};
 
 use anyhow::Context;
 use semver::{Op, Version, VersionReq};
-use walkdir::WalkDir;
 
 use crate::{
-    cargo::{self, DepKind},
-    command_ext::CommandExt as _,
+    branch::{Branch, BranchArgs, BranchStatus},
+    cargo::{self, DepKind, Metadata},
+    check::{self, CheckFailed, CheckResult},
+    config::Config,
+    cooldown::{self, Cooldown, HeldBack},
+    discovery::{Repo, Workspace},
+    exclude::{ExcludeArgs, ExcludePaths},
+    features::{self, FeatureFailure},
     git,
+    group::{self, Group},
+    index::Index,
+    lockfile::{Lockfile, PackageChange},
+    outdated,
+    release_notes::{self, ReleaseNotes},
 };
 
 #[derive(clap::Args, Debug)]
 pub struct UpgradeArgs {
-    /// Exclude "Cargo.lock" files or containing directories.
+    #[command(flatten)]
+    pub exclude_args: ExcludeArgs,
+
+    /// Run checks after applying upgrades, and don't commit if they fail.
     ///
-    /// Must be specified relative to the current working directory.
+    /// This is `cargo check --all-targets` unless other checks are
+    /// configured in "cargo-lockstep.toml".
     #[arg(long)]
-    exclude: Vec<String>,
+    pub check: bool,
 
-    /// Run `cargo check` after applying upgrades.
+    /// Also `cargo check` with `--all-features`, with `--no-default-features`,
+    /// and with each feature that enables an upgraded crate.
+    ///
+    /// Combinations that were already failing before the upgrade are reported
+    /// but don't stop it; ones that only fail afterwards do.
     #[arg(long)]
-    check: bool,
+    pub check_features: bool,
+
+    /// If checks fail in a workspace, put it back how it was and carry on
+    /// with the rest rather than stopping.
+    ///
+    /// Everything left behind on the old versions is listed at the end.
+    #[arg(long)]
+    pub skip_failing: bool,
+
+    /// Don't adopt versions that were published less than this many days ago.
+    ///
+    /// Crates are upgraded to the newest release that is old enough instead,
+    /// and new transitive dependencies are held back the same way.
+    #[arg(long, value_name = "DAYS")]
+    pub min_age: Option<u32>,
+
+    /// Upgrade a group of crates that must move together.
+    ///
+    /// Either the name of a group from "cargo-lockstep.toml",
+    /// or a comma-separated list of crate names.
+    /// May be specified multiple times.
+    #[arg(long = "group", value_name = "GROUP")]
+    pub groups: Vec<String>,
+
+    /// Upgrade every direct dependency that has a newer semver-incompatible release.
+    ///
+    /// Each crate (or group) gets its own commit, so that individual
+    /// upgrades can be reverted if they cause trouble.
+    #[arg(long, conflicts_with = "dep_crate_names")]
+    pub all: bool,
+
+    /// Don't upgrade this crate when using `--all`.
+    #[arg(long, requires = "all", value_name = "CRATE")]
+    pub exclude_crate: Vec<String>,
+
+    #[command(flatten)]
+    pub branch_args: BranchArgs,
 
     /// Name of crates to upgrade.
-    dep_crate_names: Vec<String>,
+    #[arg(required_unless_present_any = ["all", "groups"])]
+    pub dep_crate_names: Vec<String>,
+}
+
+/// Everything we've decided to upgrade, and to which versions.
+pub struct UpgradePlan {
+    pub latest_versions: HashMap<String, Version>,
+    /// Versions picked for each group, by group name.
+    pub group_versions: BTreeMap<String, BTreeMap<String, Version>>,
+    /// Crates that don't have any release old enough to upgrade to.
+    pub fully_held_back: HashSet<String>,
+    pub held_back: Vec<HeldBack>,
+}
+
+/// Crates to upgrade together in a single commit.
+pub struct Batch {
+    pub crate_names: Vec<String>,
+    pub group_names: Vec<String>,
+}
+
+/// What happened when applying a batch.
+pub struct BatchOutcome {
+    pub crate_names: Vec<String>,
+    /// Whether anything changed, and so was committed.
+    pub committed: bool,
+    /// Everything the cooldown held back, including transitive dependencies.
+    pub held_back: Vec<HeldBack>,
+    /// Checks that passed after upgrading.
+    pub checks: Vec<CheckResult>,
+    /// Feature combinations that failed both before and after upgrading.
+    pub already_failing: Vec<FeatureFailure>,
+    /// Workspaces whose checks failed, so were put back how they were.
+    pub left_behind: Vec<LeftBehind>,
+    /// Older versions of upgraded crates that other dependencies still pull in.
+    pub old_versions: Vec<OldVersion>,
+    /// Where to read about what changed in the upgraded crates.
+    pub release_notes: Vec<ReleaseNotes>,
+    /// Groups that weren't upgraded because some project pins a version we can't change.
+    pub skipped_groups: Vec<SkippedGroup>,
+}
+
+/// A group that was left alone.
+pub struct SkippedGroup {
+    pub group_name: String,
+    /// Each requirement that stopped it from being upgraded.
+    pub reasons: Vec<String>,
+}
+
+/// An older version of an upgraded crate that's still in a lockfile.
+pub struct OldVersion {
+    /// Workspace whose lockfile it's in.
+    pub dir: PathBuf,
+    pub crate_name: String,
+    pub version: Version,
+    /// How the workspace depends on it, as "name version" for each package on the way.
+    pub path: Vec<String>,
+}
+
+impl fmt::Display for OldVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} in {:?} ({})",
+            self.crate_name,
+            self.version,
+            self.dir,
+            self.path.join(" -> ")
+        )
+    }
+}
+
+/// A workspace that was left on the old versions.
+pub struct LeftBehind {
+    /// Root directory of the workspace.
+    pub dir: PathBuf,
+    /// Why its checks failed.
+    pub reason: String,
 }
 
-pub fn upgrade_one(upgrade_args: &UpgradeArgs) -> anyhow::Result<()> {
+/// Everything `upgrade_one` did.
+pub struct UpgradeReport {
+    /// The branch the upgrades were committed to, if we got as far as making one.
+    pub branch_name: Option<String>,
+    pub branch_status: Option<BranchStatus>,
+    pub batches: Vec<BatchOutcome>,
+}
+
+/// Upgrade the requested crates (or everything outdated) across the whole repo,
+/// committing the result on a new branch.
+pub fn upgrade_one(upgrade_args: &UpgradeArgs) -> anyhow::Result<UpgradeReport> {
     // TODO: Factor out a bunch of this stuff that's common
     // to both subcommands.
 
-    // Validate that all exclude rules point to actual paths.
-    // (It's bad to let people think that their arguments are doing something if they're not!)
-    let mut exclude_paths = Vec::new();
-    for exclude in &upgrade_args.exclude {
-        let exclude_path =
-            PathBuf::from_str(exclude).with_context(|| "\"{exclude:?}\" isn't a valid path")?;
-        if !exclude_path.exists() {
-            anyhow::bail!("Excluded path {exclude_path:?} doesn't exist!");
-        }
-        exclude_paths.push(
-            exclude_path
-                .canonicalize()
-                .with_context(|| format!("Failed to canonicalize exclude path {exclude_path:?}"))?,
-        );
-    }
+    let exclude_paths = ExcludePaths::from_args(&upgrade_args.exclude_args)?;
 
     // TODO: Find git root by default instead of just operating from CWD.
     // (Have option for operating just within CWD.)
@@ -53,66 +183,385 @@ pub fn upgrade_one(upgrade_args: &UpgradeArgs) -> anyhow::Result<()> {
         anyhow::bail!("Working tree is not clean; please commit or stash your changes first.");
     }
 
-    let latest_versions = cargo::get_latest_versions(&upgrade_args.dep_crate_names)
-        .context("Failed to get latest versions for requested crates")?;
+    let config = Config::load().context("Failed to load config")?;
+    let mut groups = upgrade_args
+        .groups
+        .iter()
+        .map(|group| Group::from_arg(group, &config))
+        .collect::<anyhow::Result<Vec<_>>>()?;
 
-    // Update all the projects we can find!
+    println!("Looking for Cargo projects...");
+    let repo = Repo::discover(&exclude_paths)?;
 
-    let base_branch = git::guess_base_branch().context("Failed to guess base branch")?;
-    git::fetch(&base_branch).context("Failed to update base branch from origin")?;
+    // Find everything with a newer incompatible release, if we were asked to upgrade everything.
+    let mut outdated_versions = HashMap::new();
+    if upgrade_args.all {
+        let outdated_crates = outdated::find_outdated(&repo)?;
+        for exclude_crate in &upgrade_args.exclude_crate {
+            if !outdated_crates
+                .iter()
+                .any(|outdated_crate| &outdated_crate.name == exclude_crate)
+            {
+                eprintln!("Warning: excluded crate {exclude_crate:?} doesn't have an upgrade available anyway");
+            }
+        }
+        for outdated_crate in outdated_crates {
+            if upgrade_args.exclude_crate.contains(&outdated_crate.name) {
+                println!(
+                    "  Skipping {:?} because it was excluded.",
+                    outdated_crate.name
+                );
+                continue;
+            }
+            outdated_versions.insert(outdated_crate.name, outdated_crate.latest_version);
+        }
+        if outdated_versions.is_empty() {
+            println!("All dependencies are already on their latest releases!");
+            return Ok(UpgradeReport {
+                branch_name: None,
+                branch_status: None,
+                batches: Vec::new(),
+            });
+        }
+    }
 
-    // Make a branch based on the current time.
-    let compact_now = chrono::Utc::now().format("%Y%m%d%H%M%S");
-    let new_branch_name = format!("cargo-lockstep-upgrade-{compact_now}");
-    git::switch_to_new_branch(&new_branch_name, &format!("origin/{base_branch}"))
-        .context("Failed to create branch for applying upgrades")?;
+    // Expanding wildcards in groups requires knowing what we depend on.
+    let need_known_dependencies = groups.iter().any(Group::has_wildcards)
+        || (upgrade_args.all && config.groups.values().flatten().any(|m| m.ends_with('*')));
+    let known_dependencies = if need_known_dependencies {
+        find_dependency_names(&repo)
+    } else {
+        BTreeSet::new()
+    };
+    let mut group_members = BTreeMap::new();
+    for group in &groups {
+        group_members.insert(group.name.clone(), group.expand(&known_dependencies)?);
+    }
 
-    // Find all the Cargo.toml files so we can upgrade all of the requested deps.
-    println!("Looking for \"Cargo.toml\" files...");
-    let mut any_changes = false;
-    for entry in WalkDir::new(".").follow_links(false).into_iter() {
-        // TODO: More helpful error.
-        let entry = entry.context("Couldn't read dir entry")?;
+    // Crates from configured groups have to bring the rest of their group with them.
+    if upgrade_args.all {
+        for group_name in config.groups.keys() {
+            if group_members.contains_key(group_name) {
+                continue;
+            }
+            let group = Group::from_arg(group_name, &config)?;
+            let Ok(members) = group.expand(&known_dependencies) else {
+                continue;
+            };
+            if members
+                .iter()
+                .any(|member| outdated_versions.contains_key(member))
+            {
+                println!("  Upgrading group {group_name:?} together because some of its members are outdated.");
+                group_members.insert(group.name.clone(), members);
+                groups.push(group);
+            }
+        }
+    }
 
-        let file_name = entry.file_name().to_string_lossy();
-        if file_name != "Cargo.toml" {
-            continue;
+    // Everything we're upgrading, in the order it was asked for.
+    let mut dep_crate_names = upgrade_args.dep_crate_names.clone();
+    let mut outdated_crate_names: Vec<_> = outdated_versions.keys().cloned().collect();
+    outdated_crate_names.sort();
+    dep_crate_names.extend(outdated_crate_names);
+    for members in group_members.values() {
+        for member in members {
+            if !dep_crate_names.contains(member) {
+                dep_crate_names.push(member.clone());
+            }
         }
+    }
 
-        let dir = entry
-            .path()
-            .parent()
-            .context("Cargo.toml file didn't have a parent directory")?;
+    let cooldown = upgrade_args.min_age.map(Cooldown::new);
+    let index = Index::from_env();
+    let plan = plan_upgrade(
+        &dep_crate_names,
+        &group_members,
+        outdated_versions,
+        &lowest_requirements(&repo),
+        &index,
+        cooldown.as_ref(),
+    )?;
 
-        let absolute_path = entry
-            .path()
-            .canonicalize()
-            .with_context(|| format!("Failed to canonicalize path {:?}", entry.path()))?;
-        if exclude_paths
+    // Normally everything goes in one commit, but when upgrading everything,
+    // make one commit per crate (or group) so that they can be reverted individually.
+    let batches = if upgrade_args.all {
+        let grouped_crate_names: HashSet<_> = group_members.values().flatten().collect();
+        group_members
             .iter()
-            .any(|exclude_path| absolute_path.starts_with(exclude_path))
-        {
-            println!("  Skipping {dir:?} because it matches an excluded path.");
-            continue;
+            .map(|(group_name, members)| Batch {
+                crate_names: members.clone(),
+                group_names: vec![group_name.clone()],
+            })
+            .chain(
+                dep_crate_names
+                    .iter()
+                    .filter(|crate_name| !grouped_crate_names.contains(crate_name))
+                    .map(|crate_name| Batch {
+                        crate_names: vec![crate_name.clone()],
+                        group_names: Vec::new(),
+                    }),
+            )
+            .collect()
+    } else {
+        vec![Batch {
+            crate_names: dep_crate_names.clone(),
+            group_names: group_members.keys().cloned().collect(),
+        }]
+    };
+
+    // Update all the projects we can find!
+
+    let branch = Branch::start(
+        upgrade_args.branch_args.branch.as_deref(),
+        "cargo-lockstep-upgrade",
+    )
+    .context("Failed to create branch for applying upgrades")?;
+
+    let mut outcomes = Vec::new();
+    for batch in &batches {
+        if batches.len() > 1 {
+            println!("Upgrading {}...", batch.crate_names.join(", "));
         }
+        outcomes.push(apply_batch(
+            batch,
+            &plan,
+            &repo,
+            upgrade_args,
+            &config,
+            &index,
+            cooldown.as_ref(),
+        )?);
+    }
 
-        println!("  Looking for dependencies to upgrade in {dir:?}...");
+    if outcomes
+        .iter()
+        .any(|outcome| !outcome.left_behind.is_empty())
+    {
+        println!("These were left on the old versions because their checks failed:");
+        for outcome in &outcomes {
+            for left_behind in &outcome.left_behind {
+                println!(
+                    "  {:?} (upgrading {})",
+                    left_behind.dir,
+                    outcome.crate_names.join(", ")
+                );
+            }
+        }
+    }
 
-        // TODO: Switch to only using `cargo metadata --no-deps` for this.
-        // And then ignore directories that don't actually have any packages
-        // directly in them.
-        let Ok(manifest) = cargo::read_manifest(dir) else {
-            eprintln!("Failed to read manifest of project to check for available upgrades; assuming it is a virtual manifest");
-            continue;
-        };
+    if outcomes
+        .iter()
+        .any(|outcome| !outcome.skipped_groups.is_empty())
+    {
+        println!("These groups were left alone because some projects pin other versions:");
+        for outcome in &outcomes {
+            for skipped_group in &outcome.skipped_groups {
+                println!(
+                    "  {}: {}",
+                    skipped_group.group_name,
+                    skipped_group.reasons.join("; ")
+                );
+            }
+        }
+    }
 
-        for dep in &manifest.dependencies {
+    if outcomes.iter().any(|outcome| !outcome.checks.is_empty()) {
+        println!("These checks passed:");
+        for outcome in &outcomes {
+            for result in &outcome.checks {
+                println!("  {result}");
+            }
+        }
+    }
+
+    let any_commits = outcomes.iter().any(|outcome| outcome.committed);
+    if any_commits {
+        println!("Upgrades applied!");
+    } else {
+        println!("Nothing was upgraded.");
+    }
+    let branch_status = branch.finish(
+        upgrade_args.branch_args.push,
+        upgrade_args.branch_args.force_with_lease,
+        any_commits,
+    )?;
+
+    Ok(UpgradeReport {
+        branch_name: Some(branch.name),
+        branch_status: Some(branch_status),
+        batches: outcomes,
+    })
+}
+
+/// Work out which version to upgrade each crate to.
+///
+/// `known_versions` are latest versions we've already looked up,
+/// so we don't need to ask again. `current_versions` are the lowest versions
+/// that the repo already requires, so that the cooldown never picks anything older.
+pub fn plan_upgrade(
+    dep_crate_names: &[String],
+    group_members: &BTreeMap<String, Vec<String>>,
+    known_versions: HashMap<String, Version>,
+    current_versions: &HashMap<String, Version>,
+    index: &Index,
+    cooldown: Option<&Cooldown>,
+) -> anyhow::Result<UpgradePlan> {
+    let grouped_crate_names: HashSet<_> = group_members.values().flatten().cloned().collect();
+    let ungrouped_crate_names: Vec<_> = dep_crate_names
+        .iter()
+        .filter(|crate_name| !grouped_crate_names.contains(*crate_name))
+        .filter(|crate_name| !known_versions.contains_key(*crate_name))
+        .cloned()
+        .collect();
+
+    let mut latest_versions = if ungrouped_crate_names.is_empty() {
+        HashMap::new()
+    } else {
+        cargo::get_latest_versions(&ungrouped_crate_names)
+            .context("Failed to get latest versions for requested crates")?
+    };
+    latest_versions.extend(
+        known_versions
+            .into_iter()
+            .filter(|(crate_name, _)| !grouped_crate_names.contains(crate_name)),
+    );
+
+    let mut held_back = Vec::new();
+    let mut fully_held_back = HashSet::new();
+    if let Some(cooldown) = cooldown {
+        for (crate_name, latest_version) in latest_versions.iter_mut() {
+            let Some(entry) = index.entry(crate_name, latest_version)? else {
+                eprintln!("Warning: {crate_name}@{latest_version} isn't in the registry index; can't check its age");
+                continue;
+            };
+            if !cooldown.is_too_new(&entry) {
+                continue;
+            }
+            let current_version = current_versions.get(crate_name);
+            let newest_allowed = cooldown
+                .newest_allowed_version(index, crate_name, |version| {
+                    current_version.is_none_or(|current_version| version > current_version)
+                })
+                .with_context(|| {
+                    format!("Failed to find an old enough version of {crate_name:?}")
+                })?;
+            println!(
+                "Holding back {crate_name:?}: {latest_version} is less than {} days old",
+                cooldown.min_age_days()
+            );
+            held_back.push(HeldBack {
+                crate_name: crate_name.clone(),
+                too_new: latest_version.clone(),
+                kept: newest_allowed.clone(),
+            });
+            match newest_allowed {
+                Some(newest_allowed) => *latest_version = newest_allowed,
+                None => {
+                    fully_held_back.insert(crate_name.clone());
+                }
+            }
+        }
+        latest_versions.retain(|crate_name, _| !fully_held_back.contains(crate_name));
+    }
+
+    // Groups are resolved straight from the index rather than via `cargo add`,
+    // because we need to pick versions that work with each other rather than just the newest.
+    // (These already respect the cooldown.)
+    let mut group_versions = BTreeMap::new();
+    for (group_name, members) in group_members {
+        let versions = group::pick_compatible_versions(index, cooldown, members)
+            .with_context(|| format!("Failed to pick versions for group {group_name:?}"))?;
+        println!("Upgrading group {group_name:?} to:");
+        for (member, version) in &versions {
+            println!("  {member}@{version}");
+        }
+        latest_versions.extend(versions.clone());
+        group_versions.insert(group_name.clone(), versions);
+    }
+
+    Ok(UpgradePlan {
+        latest_versions,
+        group_versions,
+        fully_held_back,
+        held_back,
+    })
+}
+
+/// Upgrade the crates in a batch across the whole repo, and commit the result.
+pub fn apply_batch(
+    batch: &Batch,
+    plan: &UpgradePlan,
+    repo: &Repo,
+    upgrade_args: &UpgradeArgs,
+    config: &Config,
+    index: &Index,
+    cooldown: Option<&Cooldown>,
+) -> anyhow::Result<BatchOutcome> {
+    let UpgradePlan {
+        latest_versions,
+        fully_held_back,
+        ..
+    } = plan;
+    let dep_crate_names = &batch.crate_names;
+    let mut group_versions: BTreeMap<_, _> = plan
+        .group_versions
+        .iter()
+        .filter(|(group_name, _)| batch.group_names.contains(group_name))
+        .collect();
+
+    // Upgrading only some members of a group would leave them mismatched,
+    // so leave a whole group alone if any project has a requirement that we won't rewrite.
+    let skipped_groups = find_unupgradable_groups(repo, &group_versions);
+    for skipped_group in &skipped_groups {
+        eprintln!(
+            "  Skipping group {:?}: {}",
+            skipped_group.group_name,
+            skipped_group.reasons.join("; ")
+        );
+        group_versions.remove(&skipped_group.group_name);
+    }
+    let skipped_crate_names: HashSet<_> = plan
+        .group_versions
+        .iter()
+        .filter(|(group_name, _)| {
+            skipped_groups
+                .iter()
+                .any(|skipped_group| skipped_group.group_name == **group_name)
+        })
+        .flat_map(|(_, versions)| versions.keys())
+        .collect();
+    let mut held_back: Vec<_> = plan
+        .held_back
+        .iter()
+        .filter(|held_back| dep_crate_names.contains(&held_back.crate_name))
+        .cloned()
+        .collect();
+
+    // Go through all the projects so we can upgrade all of the requested deps.
+    let mut any_changes = false;
+    let mut manifest_dirs = Vec::new();
+    let mut upgraded_manifest_dirs = HashSet::new();
+    for project in repo.projects() {
+        let dir = &project.dir;
+        println!("  Looking for dependencies to upgrade in {dir:?}...");
+        manifest_dirs.push(dir.clone());
+
+        for dep in &project.dependencies {
             // TODO: Make a hashset for checking this.
-            if !upgrade_args.dep_crate_names.contains(&dep.name) {
+            if !dep_crate_names.contains(&dep.name) || skipped_crate_names.contains(&dep.name) {
                 // We're not trying to upgrade this.
                 continue;
             }
 
+            if fully_held_back.contains(&dep.name) {
+                println!(
+                    "{:?} has no releases old enough to upgrade to; skipping",
+                    dep.name
+                );
+                continue;
+            }
+
             // Prepare a candiate version based on what we found above.
             let Some(candidate_version) = latest_versions.get(&dep.name) else {
                 eprintln!(
@@ -178,6 +627,7 @@ pub fn upgrade_one(upgrade_args: &UpgradeArgs) -> anyhow::Result<()> {
                 &extra_args,
             )
             .context("Failed to update dependency version")?;
+            upgraded_manifest_dirs.insert(dir.clone());
         }
 
         any_changes = true;
@@ -187,50 +637,117 @@ pub fn upgrade_one(upgrade_args: &UpgradeArgs) -> anyhow::Result<()> {
         println!("All specified dependencies were already on their latest versions!");
     }
 
-    // Now to a pass to update lockfiles and maybe run a `cargo check`.
-    println!("Looking for \"Cargo.lock\" files...");
-    for entry in WalkDir::new(".").follow_links(false).into_iter() {
-        // TODO: More helpful error.
-        let entry = entry.context("Couldn't read dir entry")?;
-
-        let file_name = entry.file_name().to_string_lossy();
-        if file_name != "Cargo.lock" {
-            continue;
+    // Some crates only work if they're all on matching versions,
+    // so make sure we didn't skip any group members along the way.
+    let partial_upgrades = find_partial_group_upgrades(&manifest_dirs, &group_versions)?;
+    if !partial_upgrades.is_empty() {
+        for partial_upgrade in &partial_upgrades {
+            eprintln!("  {partial_upgrade}");
         }
+        git::discard_changes().context("Failed to discard partial upgrade")?;
+        anyhow::bail!(
+            "Refusing to leave {} group member(s) behind; all changes have been discarded",
+            partial_upgrades.len()
+        );
+    }
 
-        let dir = entry
-            .path()
-            .parent()
-            .context("Cargo lockfile didn't have a parent directory")?;
+    // Now to a pass to update lockfiles and maybe run checks.
+    let mut checks = Vec::new();
+    let mut already_failing = Vec::new();
+    let mut left_behind = Vec::new();
+    let mut old_versions = Vec::new();
+    let mut batch_release_notes = Vec::new();
+    for workspace in repo.lockfile_workspaces() {
+        let dir = &workspace.root;
 
-        let absolute_path = entry
-            .path()
-            .canonicalize()
-            .with_context(|| format!("Failed to canonicalize path {:?}", entry.path()))?;
-        if exclude_paths
-            .iter()
-            .any(|exclude_path| absolute_path.starts_with(exclude_path))
-        {
-            println!("  Skipping {dir:?} because it matches an excluded path.");
-            continue;
-        }
+        let before = Lockfile::read_at_head(dir)?;
 
         // `cargo metadata` forces dependency resolution, so we can run it
         // instead of requesting an update of individual dependencies.
-        let _metadata = cargo::metadata(dir, false)
+        let metadata = cargo::metadata(dir, false)
             .context("Failed to run `cargo metadata` to resolve dependencies")?;
+        // Nothing here was upgraded if every project in it was skipped,
+        // so anything old it pulls in isn't news.
+        let any_upgraded = workspace
+            .projects
+            .iter()
+            .any(|project| upgraded_manifest_dirs.contains(&project.dir));
+        if any_upgraded {
+            let upgraded_crate_names: Vec<_> = dep_crate_names
+                .iter()
+                .filter(|crate_name| !skipped_crate_names.contains(crate_name))
+                .cloned()
+                .collect();
+            for old_version in
+                find_old_versions(dir, &metadata, &upgraded_crate_names, latest_versions)
+            {
+                println!("    Still using {old_version}");
+                old_versions.push(old_version);
+            }
+        }
+
+        let mut lockfile_held_back = Vec::new();
+        if let Some(cooldown) = cooldown {
+            let mut log = Vec::new();
+            lockfile_held_back = cooldown
+                .hold_back_lockfile(index, dir, &before, &mut log)
+                .context("Failed to hold back recently published versions")?;
+            for line in &log {
+                println!("{line}");
+            }
+            for held_back in &lockfile_held_back {
+                println!("    Held back {held_back} in {dir:?}");
+            }
+        }
 
-        if upgrade_args.check {
-            println!("  Running `cargo check --all-targets` in {dir:?}...");
-            let mut cmd = Command::new("cargo");
-            cmd.args(["check", "--all-targets"]).current_dir(dir);
-            cmd.success_or_err().context("`cargo check` failed")?;
+        match check_workspace(workspace, dep_crate_names, upgrade_args, config) {
+            Ok((passed, workspace_already_failing)) => {
+                checks.extend(passed);
+                already_failing.extend(workspace_already_failing);
+                held_back.extend(lockfile_held_back);
+
+                let upgraded = upgraded_versions(&before, &Lockfile::read(dir)?, dep_crate_names);
+                // Every lockfile is likely to have made the same moves.
+                for notes in release_notes::for_changes(&metadata, &upgraded, true) {
+                    if !batch_release_notes.contains(&notes) {
+                        batch_release_notes.push(notes);
+                    }
+                }
+            }
+            // Anything else (e.g. Git failing to put the upgrade aside) could have left
+            // the tree half-restored, so it's not safe to carry on.
+            Err(err) if upgrade_args.skip_failing && check::is_check_failure(&err) => {
+                eprintln!("  Checks failed in {dir:?}, so leaving it on the old versions: {err:#}");
+                revert_workspace(workspace)
+                    .with_context(|| format!("Failed to revert upgrade in {dir:?}"))?;
+                old_versions.retain(|old_version| old_version.dir != *dir);
+                left_behind.push(LeftBehind {
+                    dir: dir.clone(),
+                    reason: format!("{err:#}"),
+                });
+            }
+            Err(err) => return Err(err),
         }
     }
 
+    if git::is_working_tree_clean()? {
+        println!("    Nothing changed; not committing.");
+        return Ok(BatchOutcome {
+            crate_names: dep_crate_names.clone(),
+            committed: false,
+            held_back,
+            checks,
+            already_failing,
+            left_behind,
+            old_versions,
+            release_notes: batch_release_notes,
+            skipped_groups,
+        });
+    }
+
     println!("    Committing updates...");
     // Heuristic for making a commit summary line that's useful but not too long.
-    let mut commit_message: String = match &upgrade_args.dep_crate_names[..] {
+    let mut commit_message: String = match &dep_crate_names[..] {
         [first, second] => format!("Upgrade {first} and {second} crates"),
         [first, second, rest @ ..] => {
             format!("Upgrade {first}, {second} and {} other crates", rest.len())
@@ -240,18 +757,317 @@ pub fn upgrade_one(upgrade_args: &UpgradeArgs) -> anyhow::Result<()> {
     };
 
     commit_message += "\n\nThese crates were upgraded:\n\n";
-    for crate_name in &upgrade_args.dep_crate_names {
+    for crate_name in dep_crate_names {
+        if fully_held_back.contains(crate_name) || skipped_crate_names.contains(crate_name) {
+            continue;
+        }
         let latest_version = latest_versions
             .get(crate_name)
             .context("Missing latest version for crate")?;
         commit_message += &format!("- {crate_name}@{latest_version}\n");
     }
 
+    if !group_versions.is_empty() {
+        commit_message += "\nThese groups were upgraded together:\n\n";
+        for (group_name, versions) in &group_versions {
+            let members: Vec<_> = versions.keys().map(String::as_str).collect();
+            commit_message += &format!("- {group_name}: {}\n", members.join(", "));
+        }
+    }
+
+    commit_message += &release_notes::commit_message_section(&batch_release_notes);
+
+    if let Some(cooldown) = cooldown {
+        commit_message += &cooldown::commit_message_section(cooldown, &held_back);
+    }
+
+    commit_message += &check::commit_message_section(&checks);
+    commit_message += &features::commit_message_section(&already_failing);
+
+    if !old_versions.is_empty() {
+        commit_message += "\nThese older versions are still pulled in by other dependencies:\n\n";
+        for old_version in &old_versions {
+            commit_message += &format!("- {old_version}\n");
+        }
+    }
+
+    if !skipped_groups.is_empty() {
+        commit_message +=
+            "\nThese groups were left alone because some projects pin other versions:\n\n";
+        for skipped_group in &skipped_groups {
+            commit_message += &format!(
+                "- {}: {}\n",
+                skipped_group.group_name,
+                skipped_group.reasons.join("; ")
+            );
+        }
+    }
+
+    if !left_behind.is_empty() {
+        commit_message += "\nThese were left on the old versions because their checks failed:\n\n";
+        for left_behind in &left_behind {
+            commit_message += &format!("- {:?}\n", left_behind.dir);
+        }
+    }
+
     commit_message += "\nThis commit was created by `cargo-lockstep`.\n";
 
     git::commit(&commit_message).context("Failed to commit changes")?;
 
-    println!("Upgrades applied! You can now push this branch and make a pull-request.");
+    Ok(BatchOutcome {
+        crate_names: dep_crate_names.clone(),
+        committed: true,
+        held_back,
+        checks,
+        already_failing,
+        left_behind,
+        old_versions,
+        release_notes: batch_release_notes,
+        skipped_groups,
+    })
+}
+
+/// How each of `crate_names` moved in a lockfile: from the newest version it had
+/// before to the newest new one.
+///
+/// This isn't the same as [`Lockfile::diff`], which only pairs up compatible versions
+/// and so would see an upgrade as one version being removed and another added.
+fn upgraded_versions(
+    before: &Lockfile,
+    after: &Lockfile,
+    crate_names: &[String],
+) -> Vec<PackageChange> {
+    let newest = |lockfile: &Lockfile, crate_name: &String, skip: Option<&Lockfile>| {
+        lockfile
+            .registry_packages()
+            .filter(|package| package.name == *crate_name)
+            .filter(|package| skip.is_none_or(|skip| !skip.packages.contains(package)))
+            .map(|package| package.version.clone())
+            .max()
+    };
+    crate_names
+        .iter()
+        .filter_map(|crate_name| {
+            let from = newest(before, crate_name, None)?;
+            let to = newest(after, crate_name, Some(before))?;
+            (from < to).then(|| PackageChange::Changed {
+                name: crate_name.clone(),
+                from,
+                to,
+            })
+        })
+        .collect()
+}
+
+/// Find versions of the crates we upgraded that are older than (and incompatible with)
+/// what we upgraded to, and how they're still being pulled in.
+fn find_old_versions(
+    dir: &Path,
+    metadata: &Metadata,
+    dep_crate_names: &[String],
+    latest_versions: &HashMap<String, Version>,
+) -> Vec<OldVersion> {
+    let mut old_versions = Vec::new();
+    for package in &metadata.packages {
+        if !package.is_from_registry() || !dep_crate_names.contains(&package.name) {
+            continue;
+        }
+        let Some(latest_version) = latest_versions.get(&package.name) else {
+            continue;
+        };
+        if package.version >= *latest_version
+            || cargo::is_semver_compatible(&package.version, latest_version)
+        {
+            continue;
+        }
+        let path = metadata
+            .path_from_workspace(&package.id)
+            .unwrap_or_default()
+            .iter()
+            .map(|package| format!("{} {}", package.name, package.version))
+            .collect();
+        old_versions.push(OldVersion {
+            dir: dir.to_owned(),
+            crate_name: package.name.clone(),
+            version: package.version.clone(),
+            path,
+        });
+    }
+    old_versions
+}
+
+/// Run whichever checks were asked for in a workspace that's just been upgraded,
+/// returning the checks that passed and feature combinations that were already failing.
+fn check_workspace(
+    workspace: &Workspace,
+    dep_crate_names: &[String],
+    upgrade_args: &UpgradeArgs,
+    config: &Config,
+) -> anyhow::Result<(Vec<CheckResult>, Vec<FeatureFailure>)> {
+    let dir = &workspace.root;
+    let mut checks = Vec::new();
+    if upgrade_args.check {
+        let mut log = Vec::new();
+        let result = check::run_checks(dir, &check::checks_for(config, dir), &mut log);
+        for line in &log {
+            println!("{line}");
+        }
+        checks.extend(result?);
+    }
+
+    if !upgrade_args.check_features {
+        return Ok((checks, Vec::new()));
+    }
+    let combinations = features::combinations(workspace, dep_crate_names);
+    let outcome = features::check_combinations(dir, &combinations)?;
+    checks.extend(outcome.passed);
+    if !outcome.already_failing.is_empty() {
+        println!("These feature combinations were already failing before the upgrade:");
+        for failure in &outcome.already_failing {
+            println!("  {failure}");
+        }
+    }
+    if !outcome.regressed.is_empty() {
+        eprintln!("These feature combinations regressed:");
+        for failure in &outcome.regressed {
+            eprintln!("  {failure}: {:#}", failure.error);
+        }
+        return Err(anyhow::Error::msg(CheckFailed(format!(
+            "{} feature combination(s) no longer build after upgrading",
+            outcome.regressed.len()
+        ))));
+    }
+    Ok((checks, outcome.already_failing))
+}
+
+/// Throw away the upgrade of every manifest in a workspace, and its lockfile.
+fn revert_workspace(workspace: &Workspace) -> anyhow::Result<()> {
+    git::discard_path_changes(&workspace.root.join("Cargo.toml"))?;
+    for project in &workspace.projects {
+        git::discard_path_changes(&project.dir.join("Cargo.toml"))?;
+    }
+    git::discard_path_changes(&workspace.root.join("Cargo.lock"))
+}
+
+/// The lowest version that any project's (caret) requirement on each crate asks for.
+fn lowest_requirements(repo: &Repo) -> HashMap<String, Version> {
+    let mut lowest: HashMap<String, Version> = HashMap::new();
+    for dep in repo.projects().flat_map(|project| &project.dependencies) {
+        let Ok(version_req) = VersionReq::parse(&dep.req) else {
+            continue;
+        };
+        let [comparator] = &version_req.comparators[..] else {
+            continue;
+        };
+        if comparator.op != Op::Caret {
+            continue;
+        }
+        let version = Version::new(
+            comparator.major,
+            comparator.minor.unwrap_or(0),
+            comparator.patch.unwrap_or(0),
+        );
+        match lowest.get_mut(&dep.name) {
+            Some(lowest) if *lowest <= version => {}
+            Some(lowest) => *lowest = version,
+            None => {
+                lowest.insert(dep.name.clone(), version);
+            }
+        }
+    }
+    lowest
+}
+
+/// Find the names of all dependencies of all projects in the repo.
+fn find_dependency_names(repo: &Repo) -> BTreeSet<String> {
+    repo.projects()
+        .flat_map(|project| &project.dependencies)
+        .map(|dep| dep.name.clone())
+        .collect()
+}
 
-    Ok(())
+/// Find groups that some project would be left behind on, because it has a requirement
+/// on a member that doesn't match the group's version and that we wouldn't rewrite
+/// (anything but a single caret requirement for an older version).
+fn find_unupgradable_groups(
+    repo: &Repo,
+    group_versions: &BTreeMap<&String, &BTreeMap<String, Version>>,
+) -> Vec<SkippedGroup> {
+    let mut skipped_groups: Vec<SkippedGroup> = Vec::new();
+    for project in repo.projects() {
+        for dep in &project.dependencies {
+            for (group_name, versions) in group_versions {
+                let Some(version) = versions.get(&dep.name) else {
+                    continue;
+                };
+                let Ok(version_req) = VersionReq::parse(&dep.req) else {
+                    continue;
+                };
+                if version_req.matches(version) {
+                    continue;
+                }
+                let will_rewrite = match &version_req.comparators[..] {
+                    [comparator] => {
+                        comparator.op == Op::Caret
+                            && Version::new(
+                                comparator.major,
+                                comparator.minor.unwrap_or(0),
+                                comparator.patch.unwrap_or(0),
+                            ) < *version
+                    }
+                    _ => false,
+                };
+                if will_rewrite {
+                    continue;
+                }
+                let reason = format!(
+                    "{:?} in {:?} requires {:?}, but the group needs {version}",
+                    dep.name, project.dir, dep.req
+                );
+                match skipped_groups
+                    .iter_mut()
+                    .find(|skipped_group| skipped_group.group_name == **group_name)
+                {
+                    Some(skipped_group) => skipped_group.reasons.push(reason),
+                    None => skipped_groups.push(SkippedGroup {
+                        group_name: (*group_name).clone(),
+                        reasons: vec![reason],
+                    }),
+                }
+            }
+        }
+    }
+    skipped_groups
+}
+
+/// Check that every project that uses a member of a group now requires
+/// the version of it that we picked for the group.
+fn find_partial_group_upgrades(
+    manifest_dirs: &[PathBuf],
+    group_versions: &BTreeMap<&String, &BTreeMap<String, Version>>,
+) -> anyhow::Result<Vec<String>> {
+    let mut problems = Vec::new();
+    if group_versions.is_empty() {
+        return Ok(problems);
+    }
+    for dir in manifest_dirs {
+        let manifest = cargo::read_manifest(dir)
+            .with_context(|| format!("Failed to re-read manifest in {dir:?}"))?;
+        for dep in &manifest.dependencies {
+            for (group_name, versions) in group_versions {
+                let Some(version) = versions.get(&dep.name) else {
+                    continue;
+                };
+                let matches = VersionReq::parse(&dep.req)
+                    .is_ok_and(|version_req| version_req.matches(version));
+                if !matches {
+                    problems.push(format!(
+                        "{:?} in {dir:?} requires {:?}, but group {group_name:?} needs {version}",
+                        dep.name, dep.req
+                    ));
+                }
+            }
+        }
+    }
+    Ok(problems)
 }
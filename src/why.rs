@@ -0,0 +1,102 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use anyhow::Context;
+use semver::{Version, VersionReq};
+
+use crate::{
+    cargo,
+    discovery::Repo,
+    exclude::{ExcludeArgs, ExcludePaths},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct WhyArgs {
+    #[command(flatten)]
+    pub exclude_args: ExcludeArgs,
+
+    /// Crate to explain, optionally with a version, e.g. `hyper`, `hyper@0.14` or `hyper@0.14.28`.
+    #[arg(value_name = "CRATE[@VERSION]")]
+    pub crate_spec: String,
+}
+
+/// How a project ends up depending on a crate.
+pub struct DependencyPath {
+    /// Workspace whose lockfile this is in.
+    pub dir: PathBuf,
+    /// Every package along the way as "name version",
+    /// from a package in the workspace to the crate in question.
+    pub packages: Vec<String>,
+}
+
+/// Every path to every matching version of the crate, by version.
+pub type WhyReport = BTreeMap<Version, Vec<DependencyPath>>;
+
+/// Explain why each version of a crate is in the repo's lockfiles.
+pub fn why(why_args: &WhyArgs) -> anyhow::Result<WhyReport> {
+    let exclude_paths = ExcludePaths::from_args(&why_args.exclude_args)?;
+    let (crate_name, version_matches) = parse_crate_spec(&why_args.crate_spec)?;
+
+    println!("Looking for Cargo projects...");
+    let repo = Repo::discover(&exclude_paths)?;
+
+    let mut report = WhyReport::new();
+    for workspace in repo.lockfile_workspaces() {
+        let dir = &workspace.root;
+        let metadata = cargo::metadata(dir, false)
+            .with_context(|| format!("Failed to resolve dependencies in {dir:?}"))?;
+        let targets = metadata
+            .packages
+            .iter()
+            .filter(|package| package.name == crate_name && version_matches(&package.version));
+        let roots: Vec<_> = metadata.workspace_packages().collect();
+        for target in targets {
+            for root in &roots {
+                let Some(path) = metadata.shortest_path(&root.id, &target.id) else {
+                    continue;
+                };
+                report
+                    .entry(target.version.clone())
+                    .or_default()
+                    .push(DependencyPath {
+                        dir: dir.clone(),
+                        packages: path
+                            .iter()
+                            .map(|package| format!("{} {}", package.name, package.version))
+                            .collect(),
+                    });
+            }
+        }
+    }
+
+    if report.is_empty() {
+        println!("No lockfile has {:?}.", why_args.crate_spec);
+        return Ok(report);
+    }
+    for (version, paths) in &report {
+        println!("{crate_name} {version}:");
+        for path in paths {
+            println!("  in {:?}: {}", path.dir, path.packages.join(" -> "));
+        }
+    }
+    Ok(report)
+}
+
+/// Split `name@version` into a name and a way to match versions.
+///
+/// A full version only matches itself, while a partial one like `0.14`
+/// matches everything semver-compatible with it.
+fn parse_crate_spec(crate_spec: &str) -> anyhow::Result<(&str, impl Fn(&Version) -> bool)> {
+    let (crate_name, version) = match crate_spec.split_once('@') {
+        Some((crate_name, version)) => (crate_name, Some(version)),
+        None => (crate_spec, None),
+    };
+    let req = match version {
+        None => VersionReq::STAR,
+        Some(version) => match Version::parse(version) {
+            Ok(version) => VersionReq::parse(&format!("={version}"))?,
+            Err(_) => VersionReq::parse(version)
+                .with_context(|| format!("Couldn't make sense of version {version:?}"))?,
+        },
+    };
+    Ok((crate_name, move |version: &Version| req.matches(version)))
+}
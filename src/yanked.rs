@@ -0,0 +1,146 @@
+use std::{fmt, path::Path};
+
+use semver::Version;
+
+use crate::{
+    cargo,
+    cooldown::Cooldown,
+    index::Index,
+    lockfile::{LockedPackage, Lockfile},
+};
+
+/// A yanked version that was pinned in a lockfile, and what happened to it.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct YankedPin {
+    pub crate_name: String,
+    pub yanked: Version,
+    pub outcome: YankedOutcome,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum YankedOutcome {
+    MovedTo(Version),
+    /// Nothing depends on this package any more.
+    Removed,
+    /// We couldn't find a non-yanked version that Cargo would accept.
+    StillPinned,
+}
+
+impl fmt::Display for YankedPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.crate_name, self.yanked)?;
+        match &self.outcome {
+            YankedOutcome::MovedTo(version) => write!(f, " -> {version}"),
+            YankedOutcome::Removed => write!(f, " (no longer needed)"),
+            YankedOutcome::StillPinned => write!(f, " (couldn't move to a non-yanked version)"),
+        }
+    }
+}
+
+/// Find every crates.io package in the lockfile whose resolved version has been yanked.
+///
+/// This is only a nicety on top of `cargo update`, so packages we can't look up
+/// (e.g. because the index can't be reached) are skipped with a warning.
+pub fn find_yanked(
+    index: &Index,
+    lockfile: &Lockfile,
+    log: &mut Vec<String>,
+) -> Vec<LockedPackage> {
+    let mut yanked = Vec::new();
+    for package in lockfile.crates_io_packages() {
+        let entry = match index.entry(&package.name, &package.version) {
+            Ok(Some(entry)) => entry,
+            Ok(None) => {
+                log.push(format!(
+                    "    Warning: {} isn't in the registry index; can't check if it was yanked",
+                    package.spec()
+                ));
+                continue;
+            }
+            Err(err) => {
+                log.push(format!(
+                    "    Warning: can't check if {} was yanked: {err:#}",
+                    package.spec()
+                ));
+                continue;
+            }
+        };
+        if entry.yanked {
+            yanked.push(package.clone());
+        }
+    }
+    yanked
+}
+
+/// Move every yanked package still in the lockfile in `directory` to the newest
+/// compatible version that isn't yanked.
+///
+/// This respects the cooldown if there is a version that is old enough,
+/// but a yanked version is worse than a fresh one, so it will pick
+/// a version that is too new rather than staying put.
+pub fn move_off_yanked(
+    index: &Index,
+    directory: &Path,
+    cooldown: Option<&Cooldown>,
+    log: &mut Vec<String>,
+) -> anyhow::Result<()> {
+    let lockfile = Lockfile::read(directory)?;
+    for package in find_yanked(index, &lockfile, log) {
+        let is_candidate = |version: &Version| {
+            version > &package.version && cargo::is_semver_compatible(version, &package.version)
+        };
+        let mut candidate = match cooldown {
+            Some(cooldown) => {
+                cooldown.newest_allowed_version(index, &package.name, is_candidate)?
+            }
+            None => None,
+        };
+        if candidate.is_none() {
+            candidate = index.newest_release(&package.name, |entry| is_candidate(&entry.vers))?;
+        }
+
+        if let Some(candidate) = candidate {
+            // If this fails, the package is held by a requirement we can't satisfy
+            // any other way; that gets reported by `outcomes` below.
+            let _ = cargo::update_precise(directory, &package.spec(), &candidate);
+        }
+    }
+    Ok(())
+}
+
+/// Work out what happened to each yanked package, given the lockfile after updating.
+pub fn outcomes(yanked: Vec<LockedPackage>, after: &Lockfile) -> Vec<YankedPin> {
+    yanked
+        .into_iter()
+        .map(|package| {
+            let outcome = if after.packages.contains(&package) {
+                YankedOutcome::StillPinned
+            } else {
+                after
+                    .registry_packages()
+                    .filter(|new| new.name == package.name)
+                    .filter(|new| cargo::is_semver_compatible(&new.version, &package.version))
+                    .map(|new| new.version.clone())
+                    .max()
+                    .map_or(YankedOutcome::Removed, YankedOutcome::MovedTo)
+            };
+            YankedPin {
+                crate_name: package.name,
+                yanked: package.version,
+                outcome,
+            }
+        })
+        .collect()
+}
+
+/// Describe yanked pins for a commit message.
+pub fn commit_message_section(pins: &[YankedPin]) -> String {
+    if pins.is_empty() {
+        return String::new();
+    }
+    let mut section = "\nThese were pinned to yanked versions:\n\n".to_string();
+    for pin in pins {
+        section += &format!("- {pin}\n");
+    }
+    section
+}
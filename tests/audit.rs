@@ -0,0 +1,136 @@
+mod common;
+
+use std::fs;
+
+use common::Fixture;
+
+/// `a` is on a vulnerable `itoa` and `b` is on the fixed one.
+fn projects_with_advisory() -> Fixture {
+    let fixture = Fixture::new();
+    fixture.registry.release("itoa", "1.0.0").publish();
+    fixture.registry.release("ryu", "1.0.0").publish();
+    fixture.package("a", "pa", &[("itoa", "1"), ("ryu", "1")]);
+    fixture.lock("a");
+    fixture.registry.release("itoa", "1.0.1").publish();
+    fixture.registry.release("ryu", "1.0.1").publish();
+    fixture.package("b", "pb", &[("itoa", "1")]);
+    fixture.lock("b");
+    fixture.commit_and_push("Initial commit");
+    fixture.advisory("RUSTSEC-2024-0001", "itoa", &[">= 1.0.1"]);
+    fixture
+}
+
+#[test]
+fn reports_vulnerable_versions_per_project() {
+    let fixture = projects_with_advisory();
+
+    let output = fixture.run(&["audit"]);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Vulnerable versions in \"./a\":\n  RUSTSEC-2024-0001: itoa 1.0.0 (Something bad in itoa); fixed in >=1.0.1\n"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("No advisories for anything in \"./b\"."),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn security_only_updates_just_the_vulnerable_crates() {
+    let fixture = projects_with_advisory();
+
+    fixture.run(&["update-all", "--security-only"]);
+
+    assert!(fixture
+        .current_branch()
+        .starts_with("cargo-lockstep-security-"));
+    let lockfile = fixture.read("a/Cargo.lock");
+    assert!(
+        lockfile.contains("name = \"itoa\"\nversion = \"1.0.1\""),
+        "{lockfile}"
+    );
+    assert!(
+        lockfile.contains("name = \"ryu\"\nversion = \"1.0.0\""),
+        "{lockfile}"
+    );
+    let messages = fixture.new_commit_messages();
+    assert_eq!(messages.len(), 1, "{messages:#?}");
+    assert!(messages[0].starts_with("Security updates in \"./a\""));
+    assert!(
+        messages[0].contains(
+            "These advisories no longer apply:\n\n- RUSTSEC-2024-0001: itoa 1.0.0 (Something bad in itoa)\n"
+        ),
+        "{}",
+        messages[0]
+    );
+}
+
+#[test]
+fn advisories_arent_fixed_by_updates_that_dont_reach_the_patched_versions() {
+    let fixture = Fixture::new();
+    fixture.registry.release("itoa", "1.0.0").publish();
+    fixture.package("a", "pa", &[("itoa", "1")]);
+    fixture.lock("a");
+    fixture.registry.release("itoa", "1.0.1").publish();
+    fixture.commit_and_push("Initial commit");
+    fixture.advisory("RUSTSEC-2024-0001", "itoa", &[">= 1.0.2"]);
+
+    fixture.run(&["update-all", "--security-only"]);
+
+    let lockfile = fixture.read("a/Cargo.lock");
+    assert!(
+        lockfile.contains("name = \"itoa\"\nversion = \"1.0.1\""),
+        "{lockfile}"
+    );
+    let messages = fixture.new_commit_messages();
+    assert_eq!(messages.len(), 1, "{messages:#?}");
+    assert!(
+        messages[0].contains(
+            "These advisories still apply:\n\n- RUSTSEC-2024-0001: itoa 1.0.1 (Something bad in itoa)\n"
+        ),
+        "{}",
+        messages[0]
+    );
+    assert!(!messages[0].contains("no longer apply"), "{}", messages[0]);
+}
+
+#[test]
+fn ignores_crates_from_other_registries() {
+    let fixture = Fixture::new();
+    fixture.alt_registry.release("itoa", "1.0.0").publish();
+    fixture.package("a", "pa", &[]);
+    fixture.alt_dependency("a", "itoa", "1");
+    fixture.lock("a");
+    fixture.commit_and_push("Initial commit");
+    fixture.advisory("RUSTSEC-2024-0001", "itoa", &[">= 1.0.1"]);
+
+    let output = fixture.run(&["audit"]);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("No advisories for anything in \"./a\"."),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn skips_advisories_it_cant_parse() {
+    let fixture = projects_with_advisory();
+    let broken = fixture
+        .advisory_db_dir()
+        .join("crates/ryu/RUSTSEC-2024-0002.md");
+    fs::create_dir_all(broken.parent().unwrap()).unwrap();
+    fs::write(&broken, "```toml\n[advisory\n```\n").unwrap();
+
+    let output = fixture.run(&["audit"]);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("RUSTSEC-2024-0001: itoa 1.0.0"), "{stdout}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Warning: skipping unparseable advisory"),
+        "{stderr}"
+    );
+}
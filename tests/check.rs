@@ -0,0 +1,74 @@
+mod common;
+
+use common::two_projects_with_update;
+
+#[test]
+fn runs_configured_checks_in_order_and_records_them() {
+    let fixture = two_projects_with_update(Some(
+        r#"
+[[checks]]
+run = "test -f Cargo.lock"
+
+[[checks]]
+run = "grep -q 1.0.1 Cargo.lock"
+timeout = 60
+"#,
+    ));
+
+    let output = fixture.run(&["update-all", "--check"]);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("These checks passed:"), "{stdout}");
+    let messages = fixture.new_commit_messages();
+    assert_eq!(messages.len(), 2, "{messages:#?}");
+    for message in &messages {
+        let first = message.find("- `test -f Cargo.lock`").unwrap();
+        let second = message.find("- `grep -q 1.0.1 Cargo.lock`").unwrap();
+        assert!(first < second, "{message}");
+    }
+}
+
+#[test]
+fn projects_can_have_their_own_checks() {
+    let fixture = two_projects_with_update(Some(
+        r#"
+[[checks]]
+run = "true"
+
+[projects.b]
+checks = [{ run = "echo 'b is broken' >&2; exit 3" }]
+"#,
+    ));
+
+    let output = fixture.try_run(&["update-all", "--check"]);
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("failed in \"./b\""), "{stderr}");
+    assert!(stderr.contains("b is broken"), "{stderr}");
+    let messages = fixture.new_commit_messages();
+    assert_eq!(messages.len(), 1, "{messages:#?}");
+    assert!(
+        messages[0].contains("- `true` in \"./a\""),
+        "{}",
+        messages[0]
+    );
+}
+
+#[test]
+fn checks_that_take_too_long_fail() {
+    let fixture = two_projects_with_update(Some(
+        r#"
+[[checks]]
+run = "sleep 30"
+timeout = 1
+"#,
+    ));
+
+    let output = fixture.try_run(&["upgrade", "itoa", "--check"]);
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("`sleep 30` timed out after 1s"), "{stderr}");
+    assert!(fixture.new_commit_messages().is_empty());
+}
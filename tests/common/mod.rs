@@ -0,0 +1,407 @@
+//! Throwaway Git repos and a local registry for running `cargo-lockstep` end to end
+//! without touching the network.
+
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+use flate2::{write::GzEncoder, Compression};
+use sha2::{Digest, Sha256};
+use tempfile::TempDir;
+
+/// A file-based registry that Cargo (via source replacement) and `cargo-lockstep`
+/// (via `CARGO_LOCKSTEP_INDEX`) both read from.
+pub struct Registry {
+    dir: PathBuf,
+}
+
+/// A crate version to publish to a [`Registry`].
+pub struct Release<'a> {
+    registry: &'a Registry,
+    name: String,
+    version: String,
+    deps: Vec<(String, String)>,
+    pubtime: Option<String>,
+    lib_rs: String,
+    repository: Option<String>,
+    files: Vec<(String, String)>,
+}
+
+impl Registry {
+    fn new(dir: PathBuf) -> Self {
+        fs::create_dir_all(dir.join("index")).unwrap();
+        Self { dir }
+    }
+
+    pub fn index_dir(&self) -> PathBuf {
+        self.dir.join("index")
+    }
+
+    pub fn release(&self, name: &str, version: &str) -> Release<'_> {
+        Release {
+            registry: self,
+            name: name.to_string(),
+            version: version.to_string(),
+            deps: Vec::new(),
+            pubtime: None,
+            lib_rs: String::new(),
+            repository: None,
+            files: Vec::new(),
+        }
+    }
+
+    /// Mark an already published version as yanked.
+    pub fn yank(&self, name: &str, version: &str) {
+        let path = self.index_dir().join(index_path(name));
+        let contents = fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = contents
+            .lines()
+            .map(|line| {
+                let mut entry: serde_json::Value = serde_json::from_str(line).unwrap();
+                if entry["vers"] == version {
+                    entry["yanked"] = true.into();
+                }
+                entry.to_string()
+            })
+            .collect();
+        fs::write(&path, lines.join("\n") + "\n").unwrap();
+    }
+}
+
+impl Release<'_> {
+    pub fn dep(mut self, name: &str, req: &str) -> Self {
+        self.deps.push((name.to_string(), req.to_string()));
+        self
+    }
+
+    /// Set the contents of the crate's "src/lib.rs", which is empty otherwise.
+    pub fn source(mut self, lib_rs: &str) -> Self {
+        self.lib_rs = lib_rs.to_string();
+        self
+    }
+
+    /// Set the publish time, as an RFC 3339 timestamp.
+    pub fn published(mut self, pubtime: &str) -> Self {
+        self.pubtime = Some(pubtime.to_string());
+        self
+    }
+
+    /// Set the manifest's `repository` URL.
+    pub fn repository(mut self, url: &str) -> Self {
+        self.repository = Some(url.to_string());
+        self
+    }
+
+    /// Add a file to the crate, e.g. a changelog.
+    pub fn file(mut self, path: &str, contents: &str) -> Self {
+        self.files.push((path.to_string(), contents.to_string()));
+        self
+    }
+
+    pub fn publish(self) {
+        let Self {
+            registry,
+            name,
+            version,
+            deps,
+            pubtime,
+            lib_rs,
+            repository,
+            files,
+        } = self;
+
+        let mut manifest =
+            format!("[package]\nname = \"{name}\"\nversion = \"{version}\"\nedition = \"2021\"\n");
+        if let Some(repository) = repository {
+            manifest += &format!("repository = \"{repository}\"\n");
+        }
+        manifest += "\n[dependencies]\n";
+        for (dep_name, req) in &deps {
+            manifest += &format!("{dep_name} = \"{req}\"\n");
+        }
+
+        // A ".crate" file is just a gzipped tarball with everything under "name-version/".
+        let mut archive = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        let files = [
+            ("Cargo.toml", manifest.as_str()),
+            ("src/lib.rs", lib_rs.as_str()),
+        ]
+        .into_iter()
+        .chain(
+            files
+                .iter()
+                .map(|(path, contents)| (path.as_str(), contents.as_str())),
+        );
+        for (path, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            archive
+                .append_data(
+                    &mut header,
+                    format!("{name}-{version}/{path}"),
+                    contents.as_bytes(),
+                )
+                .unwrap();
+        }
+        let crate_file = archive.into_inner().unwrap().finish().unwrap();
+        fs::write(
+            registry.dir.join(format!("{name}-{version}.crate")),
+            &crate_file,
+        )
+        .unwrap();
+
+        let mut entry = serde_json::json!({
+            "name": name,
+            "vers": version,
+            "deps": deps
+                .iter()
+                .map(|(dep_name, req)| serde_json::json!({
+                    "name": dep_name,
+                    "req": req,
+                    "features": [],
+                    "optional": false,
+                    "default_features": true,
+                    "target": null,
+                    "kind": "normal",
+                }))
+                .collect::<Vec<_>>(),
+            "cksum": Sha256::digest(&crate_file)
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<String>(),
+            "features": {},
+            "yanked": false,
+        });
+        if let Some(pubtime) = pubtime {
+            entry["pubtime"] = pubtime.into();
+        }
+
+        let path = registry.index_dir().join(index_path(&name));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut contents = fs::read_to_string(&path).unwrap_or_default();
+        contents += &entry.to_string();
+        contents += "\n";
+        fs::write(&path, contents).unwrap();
+    }
+}
+
+fn index_path(name: &str) -> String {
+    match name.len() {
+        1 => format!("1/{name}"),
+        2 => format!("2/{name}"),
+        3 => format!("3/{}/{name}", &name[..1]),
+        _ => format!("{}/{}/{name}", &name[..2], &name[2..4]),
+    }
+}
+
+/// URL of the alternate registry, which Cargo never fetches since it's replaced
+/// with [`Fixture::alt_registry`].
+pub const ALT_REGISTRY_URL: &str = "https://alt.example.com/index";
+
+/// A Git repo with a bare "origin" to fetch from, and a registry for its dependencies.
+pub struct Fixture {
+    dir: TempDir,
+    pub registry: Registry,
+    /// A registry other than crates.io, called "alt" in Cargo's config.
+    /// `cargo-lockstep` can't see its index.
+    pub alt_registry: Registry,
+}
+
+impl Fixture {
+    pub fn new() -> Self {
+        let dir = TempDir::new().unwrap();
+        let registry = Registry::new(dir.path().join("registry"));
+        let alt_registry = Registry::new(dir.path().join("alt-registry"));
+
+        let cargo_home = dir.path().join("cargo-home");
+        fs::create_dir_all(&cargo_home).unwrap();
+        fs::write(
+            cargo_home.join("config.toml"),
+            format!(
+                "[source.crates-io]\nreplace-with = \"fixture\"\n\n[source.fixture]\nlocal-registry = {:?}\n\n\
+                 [registries.alt]\nindex = \"{ALT_REGISTRY_URL}\"\n\n\
+                 [source.alt]\nregistry = \"{ALT_REGISTRY_URL}\"\nreplace-with = \"alt-local\"\n\n\
+                 [source.alt-local]\nlocal-registry = {:?}\n",
+                registry.dir, alt_registry.dir
+            ),
+        )
+        .unwrap();
+
+        let fixture = Self {
+            dir,
+            registry,
+            alt_registry,
+        };
+        fs::create_dir_all(fixture.work_dir()).unwrap();
+        fixture.command("git", &["init", "--bare", "-b", "main", "../origin.git"]);
+        fixture.git(&["init", "-b", "main"]);
+        fixture.git(&["remote", "add", "origin", "../origin.git"]);
+        fixture.write(".gitignore", "/target\n");
+        fixture
+    }
+
+    pub fn work_dir(&self) -> PathBuf {
+        self.dir.path().join("work")
+    }
+
+    pub fn write(&self, path: &str, contents: &str) {
+        let path = self.work_dir().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    pub fn read(&self, path: &str) -> String {
+        fs::read_to_string(self.work_dir().join(path)).unwrap()
+    }
+
+    /// Write a library package with registry dependencies, given as `(name, requirement)`.
+    pub fn package(&self, dir: &str, name: &str, deps: &[(&str, &str)]) {
+        let mut manifest = format!(
+            "[package]\nname = \"{name}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\n"
+        );
+        for (dep_name, req) in deps {
+            manifest += &format!("{dep_name} = \"{req}\"\n");
+        }
+        self.write(&format!("{dir}/Cargo.toml"), &manifest);
+        self.write(&format!("{dir}/src/lib.rs"), "");
+    }
+
+    /// Add a dependency from the alternate registry to the package in `dir`.
+    pub fn alt_dependency(&self, dir: &str, dep_name: &str, req: &str) {
+        let mut manifest = self.read(&format!("{dir}/Cargo.toml"));
+        manifest += &format!("{dep_name} = {{ version = \"{req}\", registry = \"alt\" }}\n");
+        self.write(&format!("{dir}/Cargo.toml"), &manifest);
+    }
+
+    /// Add an advisory to the RustSec database in Cargo's home directory,
+    /// where `cargo-lockstep` looks by default.
+    pub fn advisory(&self, id: &str, crate_name: &str, patched: &[&str]) {
+        let path = self
+            .advisory_db_dir()
+            .join(format!("crates/{crate_name}/{id}.md"));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(
+            path,
+            format!(
+                "```toml\n[advisory]\nid = \"{id}\"\npackage = \"{crate_name}\"\ndate = 2024-01-01\n\n[versions]\npatched = {patched:?}\n```\n\n# Something bad in {crate_name}\n\nDetails.\n"
+            ),
+        )
+        .unwrap();
+    }
+
+    /// Where `audit` looks for the RustSec database by default.
+    pub fn advisory_db_dir(&self) -> PathBuf {
+        self.dir.path().join("cargo-home/advisory-db")
+    }
+
+    /// Resolve a lockfile for the package in `dir` against what's in the registry right now.
+    pub fn lock(&self, dir: &str) {
+        self.command_in(&self.work_dir().join(dir), "cargo", &["generate-lockfile"]);
+    }
+
+    /// Commit everything and push it to "origin", so it's the base for new branches.
+    pub fn commit_and_push(&self, message: &str) {
+        self.git(&["add", "-A"]);
+        self.git(&["commit", "-q", "-m", message]);
+        self.git(&["push", "-q", "origin", "main"]);
+    }
+
+    pub fn git(&self, args: &[&str]) -> String {
+        self.command("git", args)
+    }
+
+    pub fn current_branch(&self) -> String {
+        self.git(&["branch", "--show-current"]).trim().to_string()
+    }
+
+    /// Full commit messages on the current branch since "main", oldest first.
+    pub fn new_commit_messages(&self) -> Vec<String> {
+        let log = self.git(&["log", "--reverse", "--format=%B%x00", "main..HEAD"]);
+        log.split('\0')
+            .map(str::trim)
+            .filter(|message| !message.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Run `cargo-lockstep` in the repo, and panic if it fails.
+    pub fn run(&self, args: &[&str]) -> Output {
+        let output = self.try_run(args);
+        assert!(
+            output.status.success(),
+            "cargo-lockstep {args:?} failed:\n{}\n{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        output
+    }
+
+    pub fn try_run(&self, args: &[&str]) -> Output {
+        self.env(Command::new(env!("CARGO_BIN_EXE_cargo-lockstep")))
+            .args(args)
+            .current_dir(self.work_dir())
+            .output()
+            .unwrap()
+    }
+
+    fn command(&self, program: &str, args: &[&str]) -> String {
+        self.command_in(&self.work_dir(), program, args)
+    }
+
+    fn command_in(&self, dir: &Path, program: &str, args: &[&str]) -> String {
+        let output = self
+            .env(Command::new(program))
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{program} {args:?} failed:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    /// Keep everything inside the fixture: our own Cargo config and registry,
+    /// no network, and no Git config from whoever is running the tests.
+    fn env(&self, mut cmd: Command) -> Command {
+        cmd.env("CARGO_HOME", self.dir.path().join("cargo-home"))
+            .env("CARGO_TARGET_DIR", self.dir.path().join("target"))
+            .env("CARGO_NET_OFFLINE", "true")
+            .env("CARGO_LOCKSTEP_INDEX", self.registry.index_dir())
+            .env("GIT_CONFIG_GLOBAL", "/dev/null")
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_AUTHOR_NAME", "Test")
+            .env("GIT_AUTHOR_EMAIL", "test@example.com")
+            .env("GIT_COMMITTER_NAME", "Test")
+            .env("GIT_COMMITTER_EMAIL", "test@example.com")
+            .env_remove("RUST_BACKTRACE");
+        cmd
+    }
+}
+
+/// Two projects, each with their own lockfile pinned to `itoa` 1.0.0,
+/// and a newer compatible release available.
+///
+/// `config` is written to "cargo-lockstep.toml" and committed along with them.
+pub fn two_projects_with_update(config: Option<&str>) -> Fixture {
+    let fixture = Fixture::new();
+    fixture.registry.release("itoa", "1.0.0").publish();
+    fixture.package("a", "pa", &[("itoa", "1")]);
+    fixture.package("b", "pb", &[("itoa", "1")]);
+    fixture.lock("a");
+    fixture.lock("b");
+    if let Some(config) = config {
+        fixture.write("cargo-lockstep.toml", config);
+    }
+    fixture.commit_and_push("Initial commit");
+    fixture.registry.release("itoa", "1.0.1").publish();
+    fixture
+}
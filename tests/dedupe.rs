@@ -0,0 +1,90 @@
+mod common;
+
+use common::Fixture;
+
+/// A project that has both `winsys` 1 and 2 in its lockfile, because `mio` 1.0.0 uses the old one.
+/// `mio` 1.0.1 (published after locking) uses the new one.
+fn project_with_duplicates() -> Fixture {
+    let fixture = Fixture::new();
+    fixture.registry.release("winsys", "1.0.0").publish();
+    fixture.registry.release("winsys", "2.0.0").publish();
+    fixture
+        .registry
+        .release("mio", "1.0.0")
+        .dep("winsys", "1")
+        .publish();
+    fixture.package("a", "pa", &[("mio", "1"), ("winsys", "2")]);
+    fixture.lock("a");
+    fixture.commit_and_push("Initial commit");
+    fixture
+        .registry
+        .release("mio", "1.0.1")
+        .dep("winsys", "2")
+        .publish();
+    fixture
+}
+
+#[test]
+fn suggests_updates_that_collapse_duplicates() {
+    let fixture = project_with_duplicates();
+
+    let output = fixture.run(&["dedupe"]);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Duplicates in \"./a\":\n  winsys 1.0.0, 2.0.0"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("mio 1.0.0: update to 1.0.1 (`cargo update -p mio@1.0.0 --precise 1.0.1`)"),
+        "{stdout}"
+    );
+    assert_eq!(fixture.current_branch(), "main");
+    assert!(fixture.git(&["status", "--porcelain"]).is_empty());
+}
+
+#[test]
+fn applies_updates_that_collapse_duplicates() {
+    let fixture = project_with_duplicates();
+
+    fixture.run(&["dedupe", "--apply"]);
+
+    let lockfile = fixture.read("a/Cargo.lock");
+    assert!(!lockfile.contains("version = \"1.0.0\""), "{lockfile}");
+    let messages = fixture.new_commit_messages();
+    assert_eq!(messages.len(), 1, "{messages:#?}");
+    assert!(messages[0].starts_with("Deduplicate crates in \"./a\""));
+    assert!(
+        messages[0].contains("- mio 1.0.0 -> 1.0.1"),
+        "{}",
+        messages[0]
+    );
+    assert!(!messages[0].contains("still have more than one version"));
+}
+
+#[test]
+fn points_out_duplicates_that_need_bigger_upgrades() {
+    let fixture = project_with_duplicates();
+    fixture
+        .registry
+        .release("legacy", "1.0.0")
+        .dep("winsys", "1")
+        .publish();
+    fixture.package("b", "pb", &[("legacy", "1"), ("winsys", "2")]);
+    fixture.package("c", "pc", &[("winsys", "1"), ("mio", "1")]);
+    fixture.lock("b");
+    fixture.lock("c");
+    fixture.commit_and_push("More projects");
+
+    let output = fixture.run(&["dedupe"]);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("legacy 1.0.0: no compatible release uses winsys 2.0.0"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("pc 0.1.0: upgrade its requirement (`cargo-lockstep upgrade winsys`)"),
+        "{stdout}"
+    );
+}
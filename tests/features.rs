@@ -0,0 +1,135 @@
+mod common;
+
+use common::Fixture;
+
+/// A project that only uses `itoa` behind its "fmt" feature.
+fn project_with_optional_dep(itoa_2_source: &str, extra_lib_rs: &str) -> Fixture {
+    let fixture = Fixture::new();
+    fixture
+        .registry
+        .release("itoa", "1.0.0")
+        .source("pub fn old() {}")
+        .publish();
+    fixture
+        .registry
+        .release("itoa", "2.0.0")
+        .source(itoa_2_source)
+        .publish();
+    fixture.write(
+        "a/Cargo.toml",
+        r#"[package]
+name = "pa"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+itoa = { version = "1", optional = true }
+
+[features]
+fmt = ["dep:itoa"]
+everything = ["fmt"]
+broken = []
+"#,
+    );
+    fixture.write(
+        "a/src/lib.rs",
+        &format!("#[cfg(feature = \"fmt\")]\npub fn f() {{\n    itoa::old();\n}}\n{extra_lib_rs}"),
+    );
+    fixture.lock("a");
+    fixture.commit_and_push("Initial commit");
+    fixture
+}
+
+#[test]
+fn reports_feature_combinations_that_regressed() {
+    let fixture = project_with_optional_dep("pub fn new() {}", "");
+
+    let output = fixture.try_run(&["upgrade", "itoa", "--check-features"]);
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--all-features in \"./a\""), "{stderr}");
+    assert!(stderr.contains("pa/fmt in \"./a\""), "{stderr}");
+    assert!(stderr.contains("pa/everything in \"./a\""), "{stderr}");
+    assert!(!stderr.contains("--no-default-features in"), "{stderr}");
+    assert!(!stderr.contains("pa/broken"), "{stderr}");
+    assert!(
+        stderr.contains("3 feature combination(s) no longer build"),
+        "{stderr}"
+    );
+    assert!(fixture.new_commit_messages().is_empty());
+    // The upgrade is left in place to look at.
+    assert!(fixture.read("a/Cargo.toml").contains("2.0.0"));
+}
+
+#[test]
+fn tolerates_feature_combinations_that_were_already_broken() {
+    let fixture = project_with_optional_dep(
+        "pub fn old() {}",
+        "#[cfg(feature = \"broken\")]\ncompile_error!(\"broken\");\n",
+    );
+
+    let output = fixture.run(&["upgrade", "itoa", "--check-features"]);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("already failing"), "{stdout}");
+    let messages = fixture.new_commit_messages();
+    assert_eq!(messages.len(), 1, "{messages:#?}");
+    assert!(
+        messages[0]
+            .contains("already failing before this upgrade:\n\n- --all-features in \"./a\"\n"),
+        "{}",
+        messages[0]
+    );
+    assert!(
+        messages[0].contains("- `cargo check --all-targets --package pa --features fmt`"),
+        "{}",
+        messages[0]
+    );
+}
+
+#[test]
+fn skips_workspaces_that_dont_use_the_upgraded_crates() {
+    let fixture = project_with_optional_dep("pub fn old() {}", "");
+    fixture.write(
+        "c/Cargo.toml",
+        "[package]\nname = \"pc\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[features]\nbroken = []\n",
+    );
+    fixture.write(
+        "c/src/lib.rs",
+        "#[cfg(feature = \"broken\")]\ncompile_error!(\"broken\");\n",
+    );
+    fixture.lock("c");
+    fixture.commit_and_push("Add unrelated project");
+
+    let output = fixture.run(&["upgrade", "itoa", "--check-features"]);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("--all-features in \"./a\""), "{stdout}");
+    assert!(!stdout.contains("--all-features in \"./c\""), "{stdout}");
+}
+
+#[test]
+fn leaves_other_stashes_alone_when_nothing_changed() {
+    let fixture = project_with_optional_dep(
+        "pub fn old() {}",
+        "#[cfg(feature = \"broken\")]\ncompile_error!(\"broken\");\n",
+    );
+    // Pinned, so there's nothing to upgrade, but the feature checks still run.
+    let manifest = fixture.read("a/Cargo.toml");
+    fixture.write(
+        "a/Cargo.toml",
+        &manifest.replace("version = \"1\"", "version = \"=1.0.0\""),
+    );
+    fixture.lock("a");
+    fixture.commit_and_push("Pin itoa");
+    fixture.write("a/src/lib.rs", "// Someone else's work in progress\n");
+    fixture.git(&["stash", "push", "--quiet"]);
+
+    let output = fixture.run(&["upgrade", "itoa", "--check-features"]);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("already failing"), "{stdout}");
+    assert!(fixture.git(&["status", "--porcelain"]).is_empty());
+    assert_eq!(fixture.git(&["stash", "list"]).lines().count(), 1);
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use common::Fixture;
+
+fn project_with_upgrade() -> Fixture {
+    let fixture = Fixture::new();
+    fixture.registry.release("itoa", "1.0.0").publish();
+    fixture.registry.release("itoa", "2.0.0").publish();
+    fixture.package("a", "pa", &[("itoa", "1")]);
+    fixture.lock("a");
+    fixture.commit_and_push("Initial commit");
+    fixture
+}
+
+fn remote_branch_commit(fixture: &Fixture, branch: &str) -> Option<String> {
+    let output = fixture.git(&["ls-remote", "--heads", "origin", branch]);
+    output.split_whitespace().next().map(str::to_string)
+}
+
+#[test]
+fn pushes_new_branch_with_upstream() {
+    let fixture = project_with_upgrade();
+
+    fixture.run(&["upgrade", "itoa", "--push"]);
+
+    let branch = fixture.current_branch();
+    let head = fixture.git(&["rev-parse", "HEAD"]).trim().to_string();
+    assert_eq!(remote_branch_commit(&fixture, &branch), Some(head));
+    let upstream = fixture.git(&["rev-parse", "--abbrev-ref", "@{upstream}"]);
+    assert_eq!(upstream.trim(), format!("origin/{branch}"));
+}
+
+#[test]
+fn refuses_to_overwrite_remote_branch_without_force_with_lease() {
+    let fixture = project_with_upgrade();
+    let args = ["update-all", "--branch", "lockstep/update-all", "--push"];
+    fixture.registry.release("itoa", "1.0.1").publish();
+    fixture.run(&args);
+    let first_push = remote_branch_commit(&fixture, "lockstep/update-all").unwrap();
+
+    fixture.registry.release("itoa", "1.0.2").publish();
+    let output = fixture.try_run(&args);
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("use `--force-with-lease`"), "{stderr}");
+    assert_eq!(
+        remote_branch_commit(&fixture, "lockstep/update-all"),
+        Some(first_push.clone())
+    );
+
+    fixture.run(&[&args[..], &["--force-with-lease"]].concat());
+
+    let second_push = remote_branch_commit(&fixture, "lockstep/update-all").unwrap();
+    assert_ne!(second_push, first_push);
+    let lockfile = fixture.git(&["show", "origin/lockstep/update-all:a/Cargo.lock"]);
+    assert!(lockfile.contains("version = \"1.0.2\""), "{lockfile}");
+}
+
+#[test]
+fn does_not_push_unchanged_branch() {
+    let fixture = project_with_upgrade();
+    let args = ["update-all", "--branch", "lockstep/update-all", "--push"];
+    fixture.registry.release("itoa", "1.0.1").publish();
+    fixture.run(&args);
+
+    let output = fixture.run(&args);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("nothing new to push"), "{stdout}");
+}
+
+#[test]
+fn pushes_unchanged_branch_that_was_never_pushed() {
+    let fixture = project_with_upgrade();
+    let args = ["update-all", "--branch", "lockstep/update-all"];
+    fixture.registry.release("itoa", "1.0.1").publish();
+    fixture.run(&args);
+    assert_eq!(remote_branch_commit(&fixture, "lockstep/update-all"), None);
+
+    let output = fixture.run(&[&args[..], &["--push"]].concat());
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("wasn't on origin yet"), "{stdout}");
+    let head = fixture.git(&["rev-parse", "HEAD"]).trim().to_string();
+    assert_eq!(
+        remote_branch_commit(&fixture, "lockstep/update-all"),
+        Some(head)
+    );
+}
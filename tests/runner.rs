@@ -0,0 +1,151 @@
+mod common;
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use cargo_lockstep::{
+    cargo,
+    runner::{self, fake_output, FakeRunner, Invocation, Runner, SystemRunner},
+};
+use common::Fixture;
+use semver::Version;
+
+/// The runner is global, so tests that replace it mustn't overlap.
+static RUNNER_LOCK: Mutex<()> = Mutex::new(());
+
+fn with_fake_runner<T>(fake: Arc<FakeRunner>, f: impl FnOnce() -> T) -> T {
+    let _guard = RUNNER_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    runner::set_runner(fake);
+    let result = f();
+    runner::set_runner(Arc::new(SystemRunner::default()));
+    result
+}
+
+#[test]
+fn failures_include_stderr() {
+    let fake = Arc::new(FakeRunner::new(|_| {
+        fake_output(101, "", "error: failed to select a version for `itoa`")
+    }));
+
+    let err = with_fake_runner(fake.clone(), || cargo::update(Path::new("a")).unwrap_err());
+
+    let message = format!("{err:#}");
+    assert!(
+        message.contains("`cargo update (in \"a\")` failed"),
+        "{message}"
+    );
+    assert!(
+        message.contains("failed to select a version for `itoa`"),
+        "{message}"
+    );
+    assert_eq!(
+        fake.invocations(),
+        vec![Invocation {
+            program: "cargo".to_string(),
+            args: vec!["update".to_string()],
+            dir: Some(PathBuf::from("a")),
+        }]
+    );
+}
+
+#[test]
+fn failures_only_include_the_end_of_long_output() {
+    let stderr: Vec<_> = (1..=100).map(|line| format!("line {line}")).collect();
+    let stderr = stderr.join("\n");
+    let fake = Arc::new(FakeRunner::new(move |_| fake_output(101, "", &stderr)));
+
+    let err = with_fake_runner(fake, || cargo::check(Path::new("a")).unwrap_err());
+
+    let message = format!("{err:#}");
+    assert!(message.contains("[80 earlier lines omitted]"), "{message}");
+    assert!(!message.contains("line 80\n"), "{message}");
+    assert!(message.ends_with("line 100"), "{message}");
+}
+
+#[test]
+fn unknown_crates_get_a_friendly_error() {
+    let fake = Arc::new(FakeRunner::new(|invocation| {
+        if invocation.args.first().map(String::as_str) == Some("add") {
+            fake_output(
+                101,
+                "",
+                "    Updating crates.io index\nerror: the crate `serde_jsn` could not be found in registry index.",
+            )
+        } else {
+            fake_output(0, "", "")
+        }
+    }));
+
+    let err = with_fake_runner(fake, || {
+        cargo::get_latest_versions(&["serde_jsn".to_string()]).unwrap_err()
+    });
+
+    assert_eq!(
+        err.to_string(),
+        "There's no crate called \"serde_jsn\" in the registry; is it spelled correctly?"
+    );
+}
+
+#[test]
+fn latest_versions_come_from_what_cargo_add_picked() {
+    let fake = Arc::new(FakeRunner::new(|invocation| {
+        if invocation.args.first().map(String::as_str) == Some("read-manifest") {
+            fake_output(
+                0,
+                r#"{"dependencies": [{"name": "itoa", "req": "^1.0.11", "kind": null, "source": "registry+https://github.com/rust-lang/crates.io-index"}]}"#,
+                "",
+            )
+        } else {
+            fake_output(0, "", "")
+        }
+    }));
+
+    let versions = with_fake_runner(fake.clone(), || {
+        cargo::get_latest_versions(&["itoa".to_string()]).unwrap()
+    });
+
+    assert_eq!(versions["itoa"], Version::new(1, 0, 11));
+    let commands: Vec<_> = fake
+        .invocations()
+        .iter()
+        .map(|invocation| invocation.args[0].clone())
+        .collect();
+    assert_eq!(commands, ["init", "add", "read-manifest"]);
+}
+
+#[test]
+fn verbose_traces_every_command() {
+    let fixture = Fixture::new();
+    fixture.registry.release("itoa", "1.0.0").publish();
+    fixture.package("a", "pa", &[("itoa", "1")]);
+    fixture.lock("a");
+    fixture.commit_and_push("Initial commit");
+
+    let output = fixture.run(&["--verbose", "update-all"]);
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("+ git fetch origin main"), "{stderr}");
+    assert!(stderr.contains("+ cargo update (in \"./a\")"), "{stderr}");
+}
+
+#[test]
+fn timeouts_kill_everything_the_command_started() {
+    let dir = tempfile::TempDir::new().unwrap();
+
+    let err = SystemRunner::default()
+        .run_with_timeout(
+            Command::new("sh")
+                .args(["-c", "(sleep 2; touch finished) & wait"])
+                .current_dir(dir.path()),
+            Duration::from_secs(1),
+        )
+        .unwrap_err();
+
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    std::thread::sleep(Duration::from_secs(3));
+    assert!(!dir.path().join("finished").exists());
+}
@@ -0,0 +1,323 @@
+mod common;
+
+use common::{two_projects_with_update, Fixture};
+
+#[test]
+fn commits_each_lockfile_separately_on_a_new_branch() {
+    let fixture = two_projects_with_update(None);
+
+    fixture.run(&["update-all"]);
+
+    assert!(fixture
+        .current_branch()
+        .starts_with("cargo-lockstep-update-all-"));
+    for dir in ["a", "b"] {
+        let lockfile = fixture.read(&format!("{dir}/Cargo.lock"));
+        assert!(lockfile.contains("version = \"1.0.1\""), "{lockfile}");
+    }
+    let messages = fixture.new_commit_messages();
+    assert_eq!(messages.len(), 2, "{messages:#?}");
+    assert!(messages[0].starts_with("cargo update in \"./a\""));
+    assert!(messages[1].starts_with("cargo update in \"./b\""));
+}
+
+#[test]
+fn leaves_excluded_projects_alone() {
+    let fixture = two_projects_with_update(None);
+
+    fixture.run(&["update-all", "--exclude", "b/**"]);
+
+    assert!(fixture.read("a/Cargo.lock").contains("version = \"1.0.1\""));
+    assert!(fixture.read("b/Cargo.lock").contains("version = \"1.0.0\""));
+    assert_eq!(fixture.new_commit_messages().len(), 1);
+}
+
+#[test]
+fn parallel_jobs_commit_in_order() {
+    let fixture = two_projects_with_update(None);
+
+    fixture.run(&["update-all", "--jobs", "2"]);
+
+    let messages = fixture.new_commit_messages();
+    assert_eq!(messages.len(), 2, "{messages:#?}");
+    assert!(messages[0].starts_with("cargo update in \"./a\""));
+    assert!(messages[1].starts_with("cargo update in \"./b\""));
+}
+
+#[test]
+fn moves_off_yanked_versions() {
+    let fixture = Fixture::new();
+    fixture.registry.release("itoa", "1.0.0").publish();
+    fixture.package("a", "pa", &[("itoa", "1")]);
+    fixture.lock("a");
+    fixture.commit_and_push("Initial commit");
+    fixture.registry.yank("itoa", "1.0.0");
+    fixture.registry.release("itoa", "1.0.1").publish();
+
+    fixture.run(&["update-all"]);
+
+    assert!(fixture.read("a/Cargo.lock").contains("version = \"1.0.1\""));
+    let messages = fixture.new_commit_messages();
+    assert!(
+        messages[0].contains("These were pinned to yanked versions:\n\n- itoa 1.0.0 -> 1.0.1"),
+        "{}",
+        messages[0]
+    );
+}
+
+#[test]
+fn holds_back_releases_that_are_too_new() {
+    let fixture = Fixture::new();
+    fixture
+        .registry
+        .release("itoa", "1.0.0")
+        .published("2020-01-01T00:00:00Z")
+        .publish();
+    fixture.package("a", "pa", &[("itoa", "1")]);
+    fixture.lock("a");
+    fixture.commit_and_push("Initial commit");
+    fixture
+        .registry
+        .release("itoa", "1.0.1")
+        .published("2020-06-01T00:00:00Z")
+        .publish();
+    fixture
+        .registry
+        .release("itoa", "1.0.2")
+        .published(&chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string())
+        .publish();
+
+    fixture.run(&["update-all", "--min-age", "7"]);
+
+    let lockfile = fixture.read("a/Cargo.lock");
+    assert!(lockfile.contains("version = \"1.0.1\""), "{lockfile}");
+    let messages = fixture.new_commit_messages();
+    assert!(
+        messages[0].contains("- itoa 1.0.1 (skipped 1.0.2)"),
+        "{}",
+        messages[0]
+    );
+}
+
+#[test]
+fn resumes_after_a_failed_check() {
+    let fixture = two_projects_with_update(None);
+    fixture.write("b/src/lib.rs", "pub fn broken() -> u32 { \"oops\" }\n");
+    fixture.git(&["commit", "-q", "-am", "Break b"]);
+    fixture.git(&["push", "-q", "origin", "main"]);
+
+    let output = fixture.try_run(&["update-all", "--check"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--resume"), "{stderr}");
+    let branch = fixture.current_branch();
+
+    fixture.write("b/src/lib.rs", "pub fn fixed() -> u32 { 0 }\n");
+    fixture.git(&["commit", "-q", "-m", "Fix b", "--", "b/src/lib.rs"]);
+    let output = fixture.run(&["update-all", "--check", "--resume"]);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Skipping \"./a\" because it was already done"),
+        "{stdout}"
+    );
+    assert_eq!(fixture.current_branch(), branch);
+    let messages = fixture.new_commit_messages();
+    assert_eq!(messages.len(), 3, "{messages:#?}");
+    assert!(messages[0].starts_with("cargo update in \"./a\""));
+    assert_eq!(messages[1], "Fix b");
+    assert!(messages[2].starts_with("cargo update in \"./b\""));
+    assert!(fixture.read("b/Cargo.lock").contains("version = \"1.0.1\""));
+}
+
+#[test]
+fn resume_without_an_interrupted_run_fails() {
+    let fixture = two_projects_with_update(None);
+
+    let output = fixture.try_run(&["update-all", "--resume"]);
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("no interrupted run to resume"), "{stderr}");
+}
+
+#[test]
+fn stable_branch_is_reset_and_compared_with_its_previous_contents() {
+    let fixture = two_projects_with_update(None);
+    let branch = ["--branch", "lockstep/update-all"];
+
+    fixture.run(&[&["update-all"][..], &branch].concat());
+    assert_eq!(fixture.current_branch(), "lockstep/update-all");
+    fixture.git(&["push", "-q", "origin", "lockstep/update-all"]);
+
+    // Nothing new since last time.
+    let output = fixture.run(&[&["update-all"][..], &branch].concat());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("ended up the same as it was before"),
+        "{stdout}"
+    );
+    assert_eq!(fixture.new_commit_messages().len(), 2);
+
+    fixture.registry.release("itoa", "1.0.2").publish();
+    let output = fixture.run(&[&["update-all"][..], &branch].concat());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("You can now push branch \"lockstep/update-all\""),
+        "{stdout}"
+    );
+    assert_eq!(fixture.new_commit_messages().len(), 2);
+    assert!(fixture.read("a/Cargo.lock").contains("version = \"1.0.2\""));
+}
+
+#[test]
+fn refuses_to_reset_the_base_branch() {
+    let fixture = two_projects_with_update(None);
+    let main = fixture.git(&["rev-parse", "main"]);
+
+    let output = fixture.try_run(&["update-all", "--branch", "main"]);
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Refusing to commit to the base branch \"main\""),
+        "{stderr}"
+    );
+    assert_eq!(fixture.git(&["rev-parse", "main"]), main);
+}
+
+#[test]
+fn refuses_to_reset_a_branch_with_unpushed_work() {
+    let fixture = two_projects_with_update(None);
+    fixture.git(&["checkout", "-q", "-b", "lockstep/update-all"]);
+    fixture.write("notes.txt", "Don't lose me\n");
+    fixture.git(&["add", "notes.txt"]);
+    fixture.git(&["commit", "-q", "-m", "Work in progress"]);
+    let work = fixture.git(&["rev-parse", "HEAD"]);
+    fixture.git(&["checkout", "-q", "main"]);
+
+    let output = fixture.try_run(&["update-all", "--branch", "lockstep/update-all"]);
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("has 1 commits that aren't on origin"),
+        "{stderr}"
+    );
+    assert_eq!(fixture.git(&["rev-parse", "lockstep/update-all"]), work);
+}
+
+#[test]
+fn lockstep_reports_packages_that_cant_match_the_rest_of_the_repo() {
+    let fixture = Fixture::new();
+    fixture.registry.release("itoa", "1.0.0").publish();
+    fixture.registry.release("itoa", "1.0.1").publish();
+    fixture.package("a", "pa", &[("itoa", "1")]);
+    fixture.package("b", "pb", &[("itoa", "=1.0.1")]);
+    fixture.lock("a");
+    fixture.lock("b");
+    fixture.commit_and_push("Initial commit");
+    fixture.registry.release("itoa", "1.0.2").publish();
+
+    let output = fixture.run(&["update-all", "--lockstep"]);
+
+    assert!(fixture.read("a/Cargo.lock").contains("version = \"1.0.2\""));
+    assert!(fixture.read("b/Cargo.lock").contains("version = \"1.0.1\""));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("itoa 1.0.1 (couldn't move to 1.0.2: failed to select a version for the requirement `itoa = \"=1.0.1\"`) in \"./b\""),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn lockstep_with_a_cooldown_never_moves_packages_backwards() {
+    let fixture = Fixture::new();
+    fixture
+        .registry
+        .release("itoa", "1.0.0")
+        .published("2020-01-01T00:00:00Z")
+        .publish();
+    fixture.package("a", "pa", &[("itoa", "1")]);
+    fixture.lock("a");
+    fixture
+        .registry
+        .release("itoa", "1.0.1")
+        .published(&chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string())
+        .publish();
+    fixture.package("b", "pb", &[("itoa", "1")]);
+    fixture.lock("b");
+    fixture.commit_and_push("Initial commit");
+
+    let output = fixture.run(&["update-all", "--lockstep", "--min-age", "7"]);
+
+    assert!(fixture.read("a/Cargo.lock").contains("version = \"1.0.0\""));
+    assert!(fixture.read("b/Cargo.lock").contains("version = \"1.0.1\""));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("couldn't move"), "{stdout}");
+}
+
+#[test]
+fn commit_messages_list_what_changed() {
+    let fixture = Fixture::new();
+    fixture.registry.release("itoa", "1.0.0").publish();
+    fixture.registry.release("memchr", "1.0.0").publish();
+    fixture
+        .registry
+        .release("mio", "1.0.0")
+        .dep("memchr", "1")
+        .publish();
+    fixture.package("a", "pa", &[("itoa", "1"), ("mio", "1")]);
+    fixture.lock("a");
+    fixture.commit_and_push("Initial commit");
+    fixture.registry.release("itoa", "1.0.1").publish();
+    fixture.registry.release("memchr", "1.0.1").publish();
+    fixture.registry.release("ryu", "1.0.0").publish();
+    fixture
+        .registry
+        .release("mio", "1.0.1")
+        .dep("memchr", "1")
+        .dep("ryu", "1")
+        .publish();
+
+    fixture.run(&["update-all"]);
+
+    let messages = fixture.new_commit_messages();
+    assert!(
+        messages[0].contains(
+            "\nDirect dependencies:\n\n- itoa 1.0.0 → 1.0.1\n- mio 1.0.0 → 1.0.1\n\nTransitive dependencies:\n\n- memchr 1.0.0 → 1.0.1\n- ryu 1.0.0 (added)\n"
+        ),
+        "{}",
+        messages[0]
+    );
+}
+
+#[test]
+fn commit_messages_only_pair_up_compatible_versions() {
+    let fixture = Fixture::new();
+    fixture.registry.release("winsys", "1.0.0").publish();
+    fixture.registry.release("winsys", "2.0.0").publish();
+    fixture
+        .registry
+        .release("mio", "1.0.0")
+        .dep("winsys", "1")
+        .publish();
+    fixture.package("a", "pa", &[("mio", "1"), ("winsys", "2")]);
+    fixture.lock("a");
+    fixture.commit_and_push("Initial commit");
+    fixture.registry.release("winsys", "2.0.1").publish();
+    fixture
+        .registry
+        .release("mio", "1.0.1")
+        .dep("winsys", "2")
+        .publish();
+
+    fixture.run(&["update-all"]);
+
+    let messages = fixture.new_commit_messages();
+    assert!(
+        messages[0].contains("- winsys 2.0.0 → 2.0.1\n- winsys 1.0.0 (removed)\n"),
+        "{}",
+        messages[0]
+    );
+}
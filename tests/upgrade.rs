@@ -0,0 +1,387 @@
+mod common;
+
+use common::Fixture;
+
+/// Two projects on `itoa` 1, with 2.0.0 available.
+fn two_projects_with_upgrade() -> Fixture {
+    let fixture = Fixture::new();
+    fixture.registry.release("itoa", "1.0.0").publish();
+    fixture.registry.release("itoa", "2.0.0").publish();
+    fixture.package("a", "pa", &[("itoa", "1")]);
+    fixture.package("b", "pb", &[("itoa", "1")]);
+    fixture.lock("a");
+    fixture.lock("b");
+    fixture.commit_and_push("Initial commit");
+    fixture
+}
+
+#[test]
+fn upgrades_manifests_and_lockfiles_in_one_commit() {
+    let fixture = two_projects_with_upgrade();
+
+    fixture.run(&["upgrade", "itoa"]);
+
+    assert!(fixture
+        .current_branch()
+        .starts_with("cargo-lockstep-upgrade-"));
+    for dir in ["a", "b"] {
+        let manifest = fixture.read(&format!("{dir}/Cargo.toml"));
+        assert!(manifest.contains("itoa = \"2.0.0\""), "{manifest}");
+        let lockfile = fixture.read(&format!("{dir}/Cargo.lock"));
+        assert!(lockfile.contains("version = \"2.0.0\""), "{lockfile}");
+    }
+    let messages = fixture.new_commit_messages();
+    assert_eq!(messages.len(), 1, "{messages:#?}");
+    assert!(messages[0].starts_with("Upgrade itoa crate"));
+    assert!(messages[0].contains("- itoa@2.0.0"));
+}
+
+#[test]
+fn upgrades_workspace_members_with_a_shared_lockfile() {
+    let fixture = Fixture::new();
+    fixture.registry.release("itoa", "1.0.0").publish();
+    fixture.registry.release("itoa", "2.0.0").publish();
+    fixture.write(
+        "Cargo.toml",
+        "[workspace]\nmembers = [\"x\", \"y\"]\nresolver = \"2\"\n",
+    );
+    fixture.package("x", "px", &[("itoa", "1")]);
+    fixture.package("y", "py", &[("itoa", "1")]);
+    fixture.lock(".");
+    fixture.commit_and_push("Initial commit");
+
+    fixture.run(&["upgrade", "itoa"]);
+
+    assert!(fixture.read("x/Cargo.toml").contains("itoa = \"2.0.0\""));
+    assert!(fixture.read("y/Cargo.toml").contains("itoa = \"2.0.0\""));
+    let lockfile = fixture.read("Cargo.lock");
+    assert!(lockfile.contains("version = \"2.0.0\""), "{lockfile}");
+    assert!(!lockfile.contains("version = \"1.0.0\""), "{lockfile}");
+}
+
+#[test]
+fn upgrade_all_makes_one_commit_per_crate() {
+    let fixture = Fixture::new();
+    for (name, version) in [
+        ("itoa", "1.0.0"),
+        ("itoa", "2.0.0"),
+        ("memchr", "1.0.0"),
+        ("memchr", "2.0.0"),
+    ] {
+        fixture.registry.release(name, version).publish();
+    }
+    fixture.package("a", "pa", &[("itoa", "1"), ("memchr", "1")]);
+    fixture.lock("a");
+    fixture.commit_and_push("Initial commit");
+
+    fixture.run(&["upgrade", "--all"]);
+
+    let messages = fixture.new_commit_messages();
+    assert_eq!(messages.len(), 2, "{messages:#?}");
+    assert!(messages[0].starts_with("Upgrade itoa crate"));
+    assert!(messages[1].starts_with("Upgrade memchr crate"));
+}
+
+#[test]
+fn groups_move_to_mutually_compatible_versions() {
+    let fixture = Fixture::new();
+    fixture.registry.release("beta", "1.0.0").publish();
+    fixture.registry.release("beta", "2.0.0").publish();
+    fixture.registry.release("beta", "3.0.0").publish();
+    fixture
+        .registry
+        .release("alpha", "1.0.0")
+        .dep("beta", "1")
+        .publish();
+    fixture
+        .registry
+        .release("alpha", "2.0.0")
+        .dep("beta", "2")
+        .publish();
+    fixture.write(
+        "cargo-lockstep.toml",
+        "[groups]\npair = [\"alpha\", \"beta\"]\n",
+    );
+    fixture.package("a", "pa", &[("alpha", "1"), ("beta", "1")]);
+    fixture.lock("a");
+    fixture.commit_and_push("Initial commit");
+
+    fixture.run(&["upgrade", "--group", "pair"]);
+
+    let manifest = fixture.read("a/Cargo.toml");
+    assert!(manifest.contains("alpha = \"2.0.0\""), "{manifest}");
+    assert!(manifest.contains("beta = \"2.0.0\""), "{manifest}");
+    let messages = fixture.new_commit_messages();
+    assert!(
+        messages[0].contains("- pair: alpha, beta"),
+        "{}",
+        messages[0]
+    );
+}
+
+#[test]
+fn skips_groups_that_a_project_pins() {
+    let fixture = Fixture::new();
+    for version in ["1.0.0", "2.0.0"] {
+        fixture.registry.release("itoa", version).publish();
+        fixture.registry.release("alpha", version).publish();
+        fixture.registry.release("beta", version).publish();
+    }
+    fixture.write(
+        "cargo-lockstep.toml",
+        "[groups]\npair = [\"alpha\", \"beta\"]\n",
+    );
+    fixture.package("a", "pa", &[("itoa", "1"), ("alpha", "1"), ("beta", "1")]);
+    fixture.package("b", "pb", &[("beta", "=1.0.0")]);
+    fixture.lock("a");
+    fixture.lock("b");
+    fixture.commit_and_push("Initial commit");
+
+    fixture.run(&["upgrade", "itoa", "--group", "pair"]);
+
+    let manifest = fixture.read("a/Cargo.toml");
+    assert!(manifest.contains("itoa = \"2.0.0\""), "{manifest}");
+    assert!(manifest.contains("alpha = \"1\""), "{manifest}");
+    assert!(manifest.contains("beta = \"1\""), "{manifest}");
+    let messages = fixture.new_commit_messages();
+    assert!(
+        messages[0].contains(
+            "left alone because some projects pin other versions:\n\n- pair: \"beta\" in \"./b\" requires \"=1.0.0\", but the group needs 2.0.0\n"
+        ),
+        "{}",
+        messages[0]
+    );
+    // Neither the group nor "./b" moved, so their old versions aren't worth mentioning.
+    assert!(!messages[0].contains("still pulled in"), "{}", messages[0]);
+}
+
+#[test]
+fn refuses_to_run_with_uncommitted_changes() {
+    let fixture = two_projects_with_upgrade();
+    fixture.write("a/src/lib.rs", "// Work in progress\n");
+
+    let output = fixture.try_run(&["upgrade", "itoa"]);
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Working tree is not clean"), "{stderr}");
+    assert_eq!(fixture.current_branch(), "main");
+}
+
+#[test]
+fn explains_unknown_crate_names() {
+    let fixture = two_projects_with_upgrade();
+
+    let output = fixture.try_run(&["upgrade", "itoz"]);
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("There's no crate called \"itoz\" in the registry"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn outdated_reports_mixed_requirements() {
+    let fixture = Fixture::new();
+    fixture.registry.release("itoa", "1.0.0").publish();
+    fixture.registry.release("itoa", "2.0.0").publish();
+    fixture.package("a", "pa", &[("itoa", "1")]);
+    fixture.package("b", "pb", &[("itoa", "2")]);
+    fixture.commit_and_push("Initial commit");
+
+    let output = fixture.run(&["outdated"]);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("itoa 2.0.0 (used by 2 projects) [mixed versions]"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("1: pa (\"./a\")"), "{stdout}");
+}
+
+#[test]
+fn outdated_ignores_dependencies_from_other_registries() {
+    let fixture = Fixture::new();
+    fixture.registry.release("itoa", "1.0.0").publish();
+    fixture.registry.release("itoa", "2.0.0").publish();
+    fixture.alt_registry.release("private", "1.0.0").publish();
+    fixture.alt_registry.release("private", "2.0.0").publish();
+    fixture.package("a", "pa", &[("itoa", "1")]);
+    fixture.alt_dependency("a", "private", "1");
+    fixture.commit_and_push("Initial commit");
+
+    let output = fixture.run(&["outdated"]);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("itoa 2.0.0 (used by 1 projects)"),
+        "{stdout}"
+    );
+    assert!(!stdout.contains("private"), "{stdout}");
+}
+
+#[test]
+fn skip_failing_leaves_broken_projects_behind() {
+    let fixture = Fixture::new();
+    fixture
+        .registry
+        .release("itoa", "1.0.0")
+        .source("pub fn old() {}")
+        .publish();
+    fixture.registry.release("itoa", "2.0.0").publish();
+    fixture.package("a", "pa", &[("itoa", "1")]);
+    fixture.package("b", "pb", &[("itoa", "1")]);
+    fixture.write("b/src/lib.rs", "pub fn f() {\n    itoa::old();\n}\n");
+    fixture.lock("a");
+    fixture.lock("b");
+    fixture.commit_and_push("Initial commit");
+
+    let output = fixture.run(&["upgrade", "itoa", "--check", "--skip-failing"]);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("left on the old versions because their checks failed:\n  \"./b\""),
+        "{stdout}"
+    );
+    assert!(fixture.read("a/Cargo.toml").contains("itoa = \"2.0.0\""));
+    assert!(fixture.read("b/Cargo.toml").contains("itoa = \"1\""));
+    assert!(fixture.read("b/Cargo.lock").contains("version = \"1.0.0\""));
+    let messages = fixture.new_commit_messages();
+    assert_eq!(messages.len(), 1, "{messages:#?}");
+    assert!(
+        messages[0].contains("checks failed:\n\n- \"./b\"\n"),
+        "{}",
+        messages[0]
+    );
+    assert!(fixture.git(&["status", "--porcelain"]).is_empty());
+}
+
+#[test]
+fn skip_failing_drops_what_was_held_back_in_broken_projects() {
+    let fixture = Fixture::new();
+    fixture
+        .registry
+        .release("itoa", "1.0.0")
+        .source("pub fn old() {}")
+        .publish();
+    fixture
+        .registry
+        .release("itoa", "2.0.0")
+        .dep("ryu", "1")
+        .publish();
+    fixture
+        .registry
+        .release("ryu", "1.0.0")
+        .published("2020-01-01T00:00:00Z")
+        .publish();
+    fixture
+        .registry
+        .release("ryu", "1.0.1")
+        .published(&chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string())
+        .publish();
+    // Only "./b" gets `ryu` for the first time, so only it has anything to hold back.
+    fixture.package("a", "pa", &[("itoa", "1"), ("ryu", "1")]);
+    fixture.package("b", "pb", &[("itoa", "1")]);
+    fixture.write("b/src/lib.rs", "pub fn f() {\n    itoa::old();\n}\n");
+    fixture.lock("a");
+    fixture.lock("b");
+    fixture.commit_and_push("Initial commit");
+
+    fixture.run(&[
+        "upgrade",
+        "itoa",
+        "--check",
+        "--skip-failing",
+        "--min-age",
+        "7",
+    ]);
+
+    let messages = fixture.new_commit_messages();
+    assert_eq!(messages.len(), 1, "{messages:#?}");
+    assert!(messages[0].contains("- \"./b\"\n"), "{}", messages[0]);
+    assert!(!messages[0].contains("ryu"), "{}", messages[0]);
+}
+
+#[test]
+fn notes_old_versions_still_pulled_in_by_other_dependencies() {
+    let fixture = Fixture::new();
+    fixture.registry.release("itoa", "1.0.0").publish();
+    fixture.registry.release("itoa", "2.0.0").publish();
+    fixture
+        .registry
+        .release("mio", "1.0.0")
+        .dep("itoa", "1")
+        .publish();
+    fixture.package("a", "pa", &[("itoa", "1"), ("mio", "1")]);
+    fixture.lock("a");
+    fixture.commit_and_push("Initial commit");
+
+    fixture.run(&["upgrade", "itoa"]);
+
+    let messages = fixture.new_commit_messages();
+    assert!(
+        messages[0].contains(
+            "still pulled in by other dependencies:\n\n- itoa 1.0.0 in \"./a\" (pa 0.1.0 -> mio 1.0.0 -> itoa 1.0.0)\n"
+        ),
+        "{}",
+        messages[0]
+    );
+}
+
+#[test]
+fn links_release_notes_for_upgraded_crates() {
+    let fixture = Fixture::new();
+    for version in ["1.0.0", "2.0.0"] {
+        fixture
+            .registry
+            .release("itoa", version)
+            .repository("https://github.com/example/itoa.git")
+            .file(
+                "CHANGELOG.md",
+                "# Changelog\n\n## 2.0.0\n\n- Made it faster\n\n## [1.0.0] - 2024-01-01\n\n- First release\n",
+            )
+            .publish();
+    }
+    fixture.package("a", "pa", &[("itoa", "1")]);
+    fixture.lock("a");
+    fixture.commit_and_push("Initial commit");
+
+    fixture.run(&["upgrade", "itoa"]);
+
+    let messages = fixture.new_commit_messages();
+    assert!(
+        messages[0].contains(
+            "Release notes:\n\n- itoa 1.0.0 → 2.0.0\n  - https://github.com/example/itoa/compare/v1.0.0...v2.0.0\n  - https://github.com/example/itoa/blob/HEAD/CHANGELOG.md\n\n  > ## 2.0.0\n  >\n  > - Made it faster\n"
+        ),
+        "{}",
+        messages[0]
+    );
+}
+
+#[test]
+fn cooldown_never_picks_a_version_older_than_the_current_requirement() {
+    let fixture = Fixture::new();
+    fixture.registry.release("itoa", "1.0.0").publish();
+    fixture.registry.release("itoa", "2.0.0").publish();
+    fixture.package("a", "pa", &[("itoa", "2")]);
+    fixture.lock("a");
+    fixture.commit_and_push("Initial commit");
+    fixture
+        .registry
+        .release("itoa", "3.0.0")
+        .published(&chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string())
+        .publish();
+
+    let output = fixture.run(&["upgrade", "itoa", "--min-age", "7"]);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("\"itoa\" has no releases old enough to upgrade to"),
+        "{stdout}"
+    );
+    let manifest = fixture.read("a/Cargo.toml");
+    assert!(manifest.contains("itoa = \"2\""), "{manifest}");
+    assert!(fixture.new_commit_messages().is_empty());
+}
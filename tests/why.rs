@@ -0,0 +1,77 @@
+mod common;
+
+use common::Fixture;
+
+fn projects_with_two_versions() -> Fixture {
+    let fixture = Fixture::new();
+    fixture.registry.release("winsys", "1.0.0").publish();
+    fixture.registry.release("winsys", "2.0.0").publish();
+    fixture
+        .registry
+        .release("mio", "1.0.0")
+        .dep("winsys", "1")
+        .publish();
+    fixture.package("a", "pa", &[("mio", "1")]);
+    fixture.package("b", "pb", &[("winsys", "2")]);
+    fixture.lock("a");
+    fixture.lock("b");
+    fixture.commit_and_push("Initial commit");
+    fixture
+}
+
+#[test]
+fn shows_paths_to_every_version_across_the_repo() {
+    let fixture = projects_with_two_versions();
+
+    let output = fixture.run(&["why", "winsys"]);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("winsys 1.0.0:\n  in \"./a\": pa 0.1.0 -> mio 1.0.0 -> winsys 1.0.0\n"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("winsys 2.0.0:\n  in \"./b\": pb 0.1.0 -> winsys 2.0.0\n"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn narrows_down_by_version() {
+    let fixture = projects_with_two_versions();
+
+    let output = fixture.run(&["why", "winsys@2"]);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("winsys 2.0.0:"), "{stdout}");
+    assert!(!stdout.contains("winsys 1.0.0:"), "{stdout}");
+
+    let output = fixture.run(&["why", "winsys@1.0.1"]);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("No lockfile has \"winsys@1.0.1\"."),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn finds_optional_dependencies() {
+    let fixture = Fixture::new();
+    fixture.registry.release("itoa", "1.0.0").publish();
+    fixture.write(
+        "a/Cargo.toml",
+        "[package]\nname = \"pa\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\nitoa = { version = \"1\", optional = true }\n",
+    );
+    fixture.write("a/src/lib.rs", "");
+    fixture.lock("a");
+    fixture.commit_and_push("Initial commit");
+
+    let output = fixture.run(&["why", "itoa"]);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("itoa 1.0.0:\n  in \"./a\": pa 0.1.0 -> itoa 1.0.0\n"),
+        "{stdout}"
+    );
+}